@@ -11,6 +11,11 @@ fn uncontended(c: &mut Criterion) {
         b.iter(|| drop(black_box(priority.try_acquire(0).unwrap())))
     });
 
+    let borrowed = PrioritySemaphore::new(1);
+    group.bench_function("priority_semaphore_ref", |b| {
+        b.iter(|| drop(black_box(borrowed.try_acquire_ref(0).unwrap())))
+    });
+
     let tokio = Arc::new(tokio::sync::Semaphore::new(1));
     group.bench_function("tokio_semaphore", |b| {
         b.iter(|| drop(black_box(tokio.clone().try_acquire_owned().unwrap())))
@@ -1,12 +1,62 @@
 //! Error types.
 
+use crate::policy::Busy;
+use alloc::sync::Arc;
+use core::error::Error;
+
+/// Why a semaphore was closed, as given to
+/// [`PrioritySemaphore::close_with`](crate::PrioritySemaphore::close_with).
+///
+/// Cloning shares the reason. Two reasons are equal only when they are the
+/// same shared value.
+#[derive(Debug, Clone)]
+pub struct CloseReason(Arc<dyn Error + Send + Sync>);
+
+impl CloseReason {
+    pub(crate) fn new<E: Error + Send + Sync + 'static>(reason: E) -> Self {
+        Self(Arc::new(reason))
+    }
+
+    /// Returns the reason as a trait object.
+    pub fn get(&self) -> &(dyn Error + Send + Sync + 'static) {
+        &*self.0
+    }
+
+    /// Returns the reason if it is of type `E`.
+    pub fn downcast_ref<E: Error + 'static>(&self) -> Option<&E> {
+        self.0.downcast_ref()
+    }
+}
+
+impl PartialEq for CloseReason {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for CloseReason {}
+
+impl core::fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
 /// Returned by `try_acquire` when no permits are immediately available.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum TryAcquireError {
     /// All permits are currently in use.
     NoPermits,
     /// Semaphore has been closed.
     Closed,
+    /// Semaphore has been closed with
+    /// [`PrioritySemaphore::close_with`](crate::PrioritySemaphore::close_with).
+    ClosedWith(CloseReason),
+    /// An [`AdmissionRule`](crate::AdmissionRule) rejected the priority.
+    Rejected(Busy),
+    /// Granting is paused by
+    /// [`PrioritySemaphore::pause`](crate::PrioritySemaphore::pause).
+    Paused,
 }
 
 impl core::fmt::Display for TryAcquireError {
@@ -14,27 +64,76 @@ impl core::fmt::Display for TryAcquireError {
         match self {
             TryAcquireError::NoPermits => write!(f, "no permits available"),
             TryAcquireError::Closed => write!(f, "semaphore closed"),
+            TryAcquireError::ClosedWith(reason) => write!(f, "semaphore closed: {reason}"),
+            TryAcquireError::Rejected(reason) => write!(f, "rejected: {reason}"),
+            TryAcquireError::Paused => write!(f, "semaphore paused"),
         }
     }
 }
 
 #[cfg(feature = "std")]
-impl std::error::Error for TryAcquireError {}
+impl std::error::Error for TryAcquireError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TryAcquireError::ClosedWith(reason) => Some(reason.get()),
+            _ => None,
+        }
+    }
+}
 
 /// Returned by async `acquire`.
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum AcquireError {
     /// Semaphore was closed before acquisition succeeded.
     Closed,
+    /// Semaphore was closed with
+    /// [`PrioritySemaphore::close_with`](crate::PrioritySemaphore::close_with)
+    /// before acquisition succeeded.
+    ClosedWith(CloseReason),
+    /// The acquisition did not succeed before its timeout expired.
+    Timeout,
+    /// The deadline given to
+    /// [`DeadlineSemaphore::acquire_by`](crate::DeadlineSemaphore::acquire_by)
+    /// passed before a permit was granted.
+    DeadlineExpired,
+    /// The wait queue was full and the acquisition did not outrank any
+    /// queued waiter. See
+    /// [`PrioritySemaphore::set_max_queued`](crate::PrioritySemaphore::set_max_queued).
+    QueueFull,
+    /// The acquisition was queued, but was evicted from the full queue by a
+    /// higher-ranked one.
+    Evicted,
+    /// An [`AdmissionRule`](crate::AdmissionRule) rejected the priority.
+    Rejected(Busy),
+    /// The queued acquisition was cancelled by
+    /// [`PrioritySemaphore::cancel_waiters_where`](crate::PrioritySemaphore::cancel_waiters_where)
+    /// or [`PrioritySemaphore::cancel_below`](crate::PrioritySemaphore::cancel_below).
+    Cancelled,
 }
 
 impl core::fmt::Display for AcquireError {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         match self {
             AcquireError::Closed => write!(f, "semaphore closed"),
+            AcquireError::ClosedWith(reason) => write!(f, "semaphore closed: {reason}"),
+            AcquireError::Timeout => write!(f, "timed out waiting for a permit"),
+            AcquireError::DeadlineExpired => {
+                write!(f, "deadline passed before a permit was granted")
+            }
+            AcquireError::QueueFull => write!(f, "wait queue is full"),
+            AcquireError::Evicted => write!(f, "evicted from the wait queue"),
+            AcquireError::Rejected(reason) => write!(f, "rejected: {reason}"),
+            AcquireError::Cancelled => write!(f, "cancelled while queued"),
         }
     }
 }
 
 #[cfg(feature = "std")]
-impl std::error::Error for AcquireError {}
+impl std::error::Error for AcquireError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            AcquireError::ClosedWith(reason) => Some(reason.get()),
+            _ => None,
+        }
+    }
+}
@@ -5,8 +5,9 @@
 //! Runtime-agnostic priority semaphore.
 //!
 //! This crate provides [`PrioritySemaphore`], an asynchronous semaphore where
-//! waiters supply a signed priority. Higher priorities are granted returned
-//! permits before lower ones, and equal priorities use FIFO order. The
+//! waiters supply a priority: an `i32` by default, or any [`Ord`] key. Higher
+//! priorities are granted returned permits before lower ones, and equal
+//! priorities use FIFO order. The
 //! implementation uses only the standard `Future`/`Waker` contract and does
 //! not depend on a particular async runtime.
 //!
@@ -27,15 +28,36 @@ extern crate alloc;
 #[cfg(feature = "std")]
 extern crate std;
 
+#[cfg(feature = "std")]
+mod blocking;
 mod error;
 mod lock;
+mod multi;
 mod permit;
+mod policy;
 mod queue;
 mod semaphore;
+mod timer;
 mod util;
 mod waiter;
 
-pub use crate::error::{AcquireError, TryAcquireError};
-pub use crate::permit::Permit;
+pub use crate::error::{AcquireError, CloseReason, TryAcquireError};
+pub use crate::multi::{AcquireAll, AcquireAny, PermitSet, acquire_all, acquire_any};
+pub use crate::permit::{Permit, PermitRef, Revoked};
+pub use crate::policy::{
+    AdmissionRule, Aging, Busy, Cap, EarliestDeadlineFirst, HeadOfLine, PriorityFifo, PriorityLifo,
+    Reservation, RoundRobin, SchedulingPolicy, WaiterInfo,
+};
+#[cfg(feature = "std")]
+pub use crate::semaphore::DeadlineSemaphore;
 pub use crate::semaphore::{Priority, PrioritySemaphore};
-pub use crate::waiter::AcquireFuture;
+#[cfg(feature = "std")]
+pub use crate::timer::AcquireBy;
+#[cfg(feature = "async-io")]
+pub use crate::timer::AsyncIoTimer;
+#[cfg(feature = "tokio")]
+pub use crate::timer::TokioTimer;
+pub use crate::timer::{AcquireTimeout, Timer};
+pub use crate::waiter::{
+    AcquireFuture, AcquirePreemptible, AcquireRefFuture, ClosedAndIdle, PriorityHandle,
+};
@@ -0,0 +1,325 @@
+//! Acquisitions that span several semaphores.
+
+use crate::{
+    error::AcquireError,
+    permit::{Permit, PermitRef},
+    semaphore::{Priority, PrioritySemaphore},
+    waiter::{AcquireFuture, AcquireRefFuture},
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    cmp::Reverse,
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+/// Acquires one permit from each of `semaphores` at `priority`, resolving
+/// once all of them are held.
+///
+/// The semaphores are acquired one at a time in a single global order,
+/// whatever order they are passed in: deepest in the
+/// [`child`](PrioritySemaphore::child) tree first, then by address. A child's
+/// ancestors are acquired as levels of their own in that order, which is
+/// also the order a plain child acquisition climbs in. Two `acquire_all`
+/// calls over overlapping sets therefore never hold one permit each while
+/// waiting for the other's, so they cannot deadlock.
+///
+/// A semaphore listed more than once is asked for that many permits as one
+/// request. A semaphore listed together with one of its descendants is
+/// folded into it: the permits the descendant holds on it count toward its
+/// own, so it is only asked for more when it is listed more often. Once all
+/// are held, each child's permit carries its share of its ancestors' permits,
+/// exactly as a plain child acquisition would. Dropping the future returns
+/// any permits it already holds.
+///
+/// ```rust
+/// use priority_semaphore::{PrioritySemaphore, acquire_all};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let gpu = PrioritySemaphore::new(1);
+/// let db = PrioritySemaphore::new(8);
+/// let permits = acquire_all(&[&gpu, &db], 10).await.unwrap();
+/// assert_eq!(gpu.available_permits(), 0);
+/// drop(permits);
+/// # }
+/// ```
+pub fn acquire_all<'a, P: Ord + Clone>(
+    semaphores: &[&'a PrioritySemaphore<P>],
+    priority: P,
+) -> AcquireAll<'a, P> {
+    let mut levels: Vec<Level<'a, P>> = Vec::new();
+    for &semaphore in semaphores {
+        level(&mut levels, semaphore).listed += 1;
+        let mut ancestor = semaphore.parent();
+        while let Some(parent) = ancestor {
+            level(&mut levels, parent);
+            ancestor = parent.parent();
+        }
+    }
+    levels.sort_by_key(|level| (Reverse(level.depth), address(level.semaphore)));
+
+    // Children come first, so each level knows what its children need of it
+    // before passing its own needs on to its parent.
+    let mut pending = Vec::with_capacity(levels.len());
+    let mut parents = Vec::with_capacity(levels.len());
+    for index in 0..levels.len() {
+        let semaphore = levels[index].semaphore;
+        let permits = levels[index].listed.max(levels[index].implied);
+        parents.push(semaphore.parent().map(|parent| {
+            let offset = levels[index + 1..]
+                .iter()
+                .position(|level| core::ptr::eq(level.semaphore, &**parent))
+                .expect("ancestors are levels of their own");
+            levels[index + 1 + offset].implied += permits;
+            index + 1 + offset
+        }));
+        pending.push((semaphore, permits));
+    }
+    // Reversed, so the next level in acquisition order is popped first.
+    pending.reverse();
+    AcquireAll {
+        priority,
+        held: Vec::with_capacity(pending.len()),
+        pending,
+        parents,
+        current: None,
+        complete: false,
+    }
+}
+
+/// One semaphore of an [`acquire_all`] call, with its depth in the tree.
+struct Level<'a, P: Ord> {
+    semaphore: &'a PrioritySemaphore<P>,
+    depth: usize,
+    // Permits asked for by listing the semaphore itself.
+    listed: usize,
+    // Permits held on the semaphore for its listed descendants.
+    implied: usize,
+}
+
+/// Returns the level for `semaphore`, adding it if it is new.
+fn level<'l, 'a, P: Ord>(
+    levels: &'l mut Vec<Level<'a, P>>,
+    semaphore: &'a PrioritySemaphore<P>,
+) -> &'l mut Level<'a, P> {
+    match levels
+        .iter()
+        .position(|level| core::ptr::eq(level.semaphore, semaphore))
+    {
+        Some(index) => &mut levels[index],
+        None => {
+            let depth =
+                core::iter::successors(semaphore.parent(), |parent| parent.parent()).count();
+            levels.push(Level {
+                semaphore,
+                depth,
+                listed: 0,
+                implied: 0,
+            });
+            levels.last_mut().unwrap()
+        }
+    }
+}
+
+fn address<P: Ord>(semaphore: &PrioritySemaphore<P>) -> usize {
+    core::ptr::from_ref(semaphore).addr()
+}
+
+/// Future returned by [`acquire_all`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled or awaited"]
+pub struct AcquireAll<'a, P: Ord = Priority> {
+    priority: P,
+    pending: Vec<(&'a PrioritySemaphore<P>, usize)>,
+    // For each level in acquisition order, the index of its parent's level.
+    parents: Vec<Option<usize>>,
+    current: Option<AcquireRefFuture<'a, P>>,
+    held: Vec<PermitRef<'a, P>>,
+    complete: bool,
+}
+
+impl<P: Ord> Unpin for AcquireAll<'_, P> {}
+
+impl<'a, P: Ord + Clone> Future for AcquireAll<'a, P> {
+    type Output = Result<PermitSet<'a, P>, AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        assert!(!this.complete, "acquire future polled after completion");
+        loop {
+            let current = match &mut this.current {
+                Some(current) => current,
+                None => match this.pending.pop() {
+                    Some((semaphore, permits)) => this.current.insert(
+                        AcquireRefFuture::without_parent(semaphore, this.priority.clone(), permits),
+                    ),
+                    None => {
+                        this.complete = true;
+                        let permits = nest(core::mem::take(&mut this.held), &this.parents);
+                        return Poll::Ready(Ok(PermitSet { permits }));
+                    }
+                },
+            };
+            match Pin::new(current).poll(cx) {
+                Poll::Ready(Ok(permit)) => {
+                    this.current = None;
+                    this.held.push(permit);
+                }
+                Poll::Ready(Err(error)) => {
+                    this.current = None;
+                    this.pending.clear();
+                    this.held.clear();
+                    this.complete = true;
+                    return Poll::Ready(Err(error));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Hands each level's share of its parent's permits to it, so a child's
+/// guard holds its ancestors' permits the way a child acquisition does.
+///
+/// Ancestors' guards are left with the permits they were listed for beyond
+/// their descendants' needs, and are dropped when nothing is left.
+fn nest<'a, P: Ord>(
+    mut held: Vec<PermitRef<'a, P>>,
+    parents: &[Option<usize>],
+) -> Vec<PermitRef<'a, P>> {
+    // Shallowest first, so a parent already carries its own ancestors'
+    // permits when it shares them out.
+    for (index, parent) in parents.iter().enumerate().rev() {
+        if let Some(parent) = *parent {
+            let (children, ancestors) = held.split_at_mut(parent);
+            children[index].adopt_parent(&mut ancestors[0]);
+        }
+    }
+    held.retain(|permit| permit.num_permits() != 0);
+    held
+}
+
+/// Combined guard returned by [`acquire_all`]; releases every permit it
+/// holds on `Drop`.
+#[derive(Debug)]
+pub struct PermitSet<'a, P: Ord = Priority> {
+    permits: Vec<PermitRef<'a, P>>,
+}
+
+impl<'a, P: Ord> PermitSet<'a, P> {
+    /// Returns the total number of permits held across all semaphores.
+    pub fn num_permits(&self) -> usize {
+        self.permits
+            .iter()
+            .map(PermitRef::num_permits_in_tree)
+            .sum()
+    }
+
+    /// Splits the set into guards that can be released separately.
+    ///
+    /// A child semaphore's guard holds its share of its ancestors' permits,
+    /// and releasing or forgetting it releases or forgets those as well. A
+    /// listed ancestor only has a guard of its own, after its descendants',
+    /// when it was listed more often than they need of it.
+    pub fn into_permits(self) -> Vec<PermitRef<'a, P>> {
+        self.permits
+    }
+}
+
+/// Acquires a permit from whichever of `semaphores` grants one first,
+/// resolving with its index and the permit.
+///
+/// The call queues on every semaphore at `priority`, polling them in order, so
+/// one with a free permit wins without waiting. Once one grants, the other
+/// registrations are withdrawn; a permit another semaphore handed over at the
+/// same moment is passed on to that semaphore's next waiter. A semaphore
+/// that fails (for example because it was closed) is dropped from the race,
+/// and the future fails with the last error only when none are left.
+///
+/// ```rust
+/// use std::sync::Arc;
+/// use priority_semaphore::{PrioritySemaphore, acquire_any};
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let replicas = [Arc::new(PrioritySemaphore::new(0)), Arc::new(PrioritySemaphore::new(1))];
+/// let (replica, permit) = acquire_any(&replicas, 10).await.unwrap();
+/// assert_eq!(replica, 1);
+/// drop(permit);
+/// # }
+/// ```
+///
+/// # Panics
+///
+/// Panics when `semaphores` is empty.
+pub fn acquire_any<P: Ord + Clone>(
+    semaphores: &[Arc<PrioritySemaphore<P>>],
+    priority: P,
+) -> AcquireAny<P> {
+    assert!(!semaphores.is_empty(), "acquire_any needs a semaphore");
+    AcquireAny {
+        semaphores: semaphores.to_vec(),
+        priority,
+        racing: Vec::new(),
+        error: None,
+        complete: false,
+    }
+}
+
+/// Future returned by [`acquire_any`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled or awaited"]
+pub struct AcquireAny<P: Ord = Priority> {
+    semaphores: Vec<Arc<PrioritySemaphore<P>>>,
+    priority: P,
+    // One entry per semaphore once started; `None` after it failed.
+    racing: Vec<Option<AcquireFuture<P>>>,
+    error: Option<AcquireError>,
+    complete: bool,
+}
+
+impl<P: Ord> Unpin for AcquireAny<P> {}
+
+impl<P: Ord + Clone> Future for AcquireAny<P> {
+    type Output = Result<(usize, Permit<P>), AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        assert!(!this.complete, "acquire future polled after completion");
+        if this.racing.is_empty() {
+            let priority = &this.priority;
+            this.racing = this
+                .semaphores
+                .iter()
+                .map(|semaphore| Some(semaphore.acquire(priority.clone())))
+                .collect();
+        }
+        for (index, slot) in this.racing.iter_mut().enumerate() {
+            let Some(acquire) = slot else { continue };
+            match Pin::new(acquire).poll(cx) {
+                Poll::Ready(Ok(permit)) => {
+                    // Dropping the rest withdraws them, passing on any
+                    // permit that was already assigned.
+                    *slot = None;
+                    this.racing.clear();
+                    this.complete = true;
+                    return Poll::Ready(Ok((index, permit)));
+                }
+                Poll::Ready(Err(error)) => {
+                    *slot = None;
+                    this.error = Some(error);
+                }
+                Poll::Pending => {}
+            }
+        }
+        if this.racing.iter().all(Option::is_none) {
+            this.racing.clear();
+            this.complete = true;
+            let error = this.error.take().expect("every semaphore failed");
+            return Poll::Ready(Err(error));
+        }
+        Poll::Pending
+    }
+}
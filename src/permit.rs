@@ -1,22 +1,318 @@
-//! RAII guard returned by [`PrioritySemaphore::acquire`].
+//! RAII guards returned by [`PrioritySemaphore::acquire`] and
+//! [`PrioritySemaphore::acquire_ref`].
 
-use crate::semaphore::PrioritySemaphore;
-use alloc::sync::Arc;
+use crate::{
+    lock::Lock,
+    semaphore::{Priority, PrioritySemaphore},
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::{
+    future::Future,
+    pin::Pin,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+};
 
-/// Returned by successful acquire; releases permit on `Drop`.
+/// Holdings of a band limited by a [`Cap`](crate::Cap).
+///
+/// Permits granted in the band share it and discharge their count when they
+/// are returned. Charges and limit changes happen with the queue lock held;
+/// discharges do not need it.
 #[derive(Debug)]
-pub struct Permit {
-    root: Arc<PrioritySemaphore>,
+pub(crate) struct Band {
+    limit: AtomicUsize,
+    held: AtomicUsize,
 }
 
-impl Permit {
-    pub(crate) fn new(root: Arc<PrioritySemaphore>) -> Self {
-        Self { root }
+impl Band {
+    pub(crate) const fn new(limit: usize) -> Self {
+        Self {
+            limit: AtomicUsize::new(limit),
+            held: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn limit(&self) -> usize {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_limit(&self, limit: usize) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    /// Returns `true` if the band can take `permits` more.
+    pub(crate) fn has_room(&self, permits: usize) -> bool {
+        self.held.load(Ordering::Acquire).saturating_add(permits) <= self.limit()
+    }
+
+    pub(crate) fn charge(&self, permits: usize) {
+        self.held.fetch_add(permits, Ordering::AcqRel);
+    }
+
+    pub(crate) fn discharge(&self, permits: usize) {
+        self.held.fetch_sub(permits, Ordering::AcqRel);
+    }
+}
+
+/// Revocation signal shared by a preemptible permit and the queue's list of
+/// preemptible holders.
+#[derive(Debug)]
+pub(crate) struct Revocation {
+    revoked: AtomicBool,
+    waker: Lock<Option<Waker>>,
+}
+
+impl Revocation {
+    pub(crate) const fn new() -> Self {
+        Self {
+            revoked: AtomicBool::new(false),
+            waker: Lock::new(None),
+        }
+    }
+
+    pub(crate) fn is_revoked(&self) -> bool {
+        self.revoked.load(Ordering::SeqCst)
+    }
+
+    /// Marks the permit revoked and returns the waker of a task waiting in
+    /// [`Permit::revoked`], to be woken once the queue lock is released.
+    pub(crate) fn revoke(&self) -> Option<Waker> {
+        self.revoked.store(true, Ordering::SeqCst);
+        self.waker.lock().take()
+    }
+
+    fn poll(&self, cx: &mut Context<'_>) -> Poll<()> {
+        if self.is_revoked() {
+            return Poll::Ready(());
+        }
+        *self.waker.lock() = Some(cx.waker().clone());
+        // A revocation between the check and storing the waker took no waker.
+        if self.is_revoked() {
+            Poll::Ready(())
+        } else {
+            Poll::Pending
+        }
+    }
+}
+
+/// Future returned by [`Permit::revoked`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled or awaited"]
+pub struct Revoked<'a> {
+    revocation: Option<&'a Revocation>,
+}
+
+impl Future for Revoked<'_> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        match self.revocation {
+            Some(revocation) => revocation.poll(cx),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Returned by successful acquire; releases its permits on `Drop`.
+///
+/// A permit of a [child](PrioritySemaphore::child) semaphore also holds the
+/// parent's permits and returns them right after its own.
+#[derive(Debug)]
+pub struct Permit<P: Ord = Priority> {
+    root: Arc<PrioritySemaphore<P>>,
+    permits: usize,
+    band: Option<Arc<Band>>,
+    // The parent's permits for a child semaphore, returned after ours.
+    parent: Option<Box<Permit<P>>>,
+    revocation: Option<Arc<Revocation>>,
+}
+
+impl<P: Ord> Permit<P> {
+    pub(crate) fn new(
+        root: Arc<PrioritySemaphore<P>>,
+        permits: usize,
+        band: Option<Arc<Band>>,
+    ) -> Self {
+        Self {
+            root,
+            permits,
+            band,
+            parent: None,
+            revocation: None,
+        }
+    }
+
+    pub(crate) fn with_parent(mut self, parent: Permit<P>) -> Self {
+        self.parent = Some(Box::new(parent));
+        self
+    }
+
+    /// Splits `permits` off into a guard of their own, together with the
+    /// same number of the parent's permits.
+    fn split(&mut self, permits: usize) -> Self {
+        self.permits -= permits;
+        Self {
+            root: self.root.clone(),
+            permits,
+            band: self.band.clone(),
+            parent: self
+                .parent
+                .as_mut()
+                .map(|parent| Box::new(parent.split(permits))),
+            revocation: None,
+        }
+    }
+
+    /// Lists the permit among the semaphore's preemptible holders.
+    pub(crate) fn into_preemptible(mut self, priority: P) -> Self {
+        self.revocation = Some(self.root.add_holder(priority, self.permits));
+        self
+    }
+
+    /// Returns the number of permits held by this guard.
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+
+    /// Returns `true` once a higher-priority waiter has asked for a
+    /// [preemptible](PrioritySemaphore::acquire_preemptible) permit back.
+    pub fn is_revoked(&self) -> bool {
+        self.revocation
+            .as_deref()
+            .is_some_and(Revocation::is_revoked)
+    }
+
+    /// Resolves once the permit is revoked, as reported by
+    /// [`Permit::is_revoked`].
+    ///
+    /// The holder should then reach a safe point and drop the permit; nothing
+    /// takes it away by force. For a permit that is not preemptible the
+    /// future never resolves.
+    pub fn revoked(&self) -> Revoked<'_> {
+        Revoked {
+            revocation: self.revocation.as_deref(),
+        }
+    }
+
+    /// Drops the guard without returning its permits, lowering the
+    /// semaphore's capacity by [`Permit::num_permits`].
+    ///
+    /// For a child semaphore, the parent's capacity is lowered as well.
+    pub fn forget(mut self) {
+        if let Some(revocation) = self.revocation.take() {
+            self.root.remove_holder(&revocation);
+        }
+        self.root
+            .forget_held(self.permits, self.band.take().as_deref());
+        self.permits = 0;
+        if let Some(parent) = self.parent.take() {
+            parent.forget();
+        }
+    }
+}
+
+impl<P: Ord> Drop for Permit<P> {
+    fn drop(&mut self) {
+        if let Some(revocation) = self.revocation.take() {
+            self.root.remove_holder(&revocation);
+        }
+        if self.permits != 0 {
+            self.root.release_from(self.permits, self.band.as_deref());
+        }
+    }
+}
+
+/// Borrowing counterpart of [`Permit`], returned by
+/// [`PrioritySemaphore::acquire_ref`]; releases its permits on `Drop`.
+///
+/// It works with any semaphore that outlives it, including a `static` one,
+/// and does not touch a reference count.
+#[derive(Debug)]
+pub struct PermitRef<'a, P: Ord = Priority> {
+    root: &'a PrioritySemaphore<P>,
+    permits: usize,
+    band: Option<Arc<Band>>,
+    parent: Option<Box<Permit<P>>>,
+}
+
+impl<'a, P: Ord> PermitRef<'a, P> {
+    pub(crate) fn new(
+        root: &'a PrioritySemaphore<P>,
+        permits: usize,
+        band: Option<Arc<Band>>,
+    ) -> Self {
+        Self {
+            root,
+            permits,
+            band,
+            parent: None,
+        }
+    }
+
+    pub(crate) fn with_parent(mut self, parent: Permit<P>) -> Self {
+        self.parent = Some(Box::new(parent));
+        self
+    }
+
+    /// Moves this guard's share of `parent`'s permits onto it, as if they
+    /// had been acquired through this child semaphore.
+    ///
+    /// `parent` guards this semaphore's parent and already carries the
+    /// permits of its own ancestors, which are shared out alike.
+    pub(crate) fn adopt_parent(&mut self, parent: &mut PermitRef<'a, P>) {
+        let root = self
+            .root
+            .parent()
+            .expect("only a child semaphore has a parent")
+            .clone();
+        debug_assert!(core::ptr::eq(&*root, parent.root));
+        parent.permits -= self.permits;
+        self.parent = Some(Box::new(Permit {
+            root,
+            permits: self.permits,
+            band: parent.band.clone(),
+            parent: parent
+                .parent
+                .as_mut()
+                .map(|ancestor| Box::new(ancestor.split(self.permits))),
+            revocation: None,
+        }));
+    }
+
+    /// Returns the number of permits held by this guard.
+    pub fn num_permits(&self) -> usize {
+        self.permits
+    }
+
+    /// [`PermitRef::num_permits`] plus the permits it holds on ancestors.
+    pub(crate) fn num_permits_in_tree(&self) -> usize {
+        let mut total = self.permits;
+        let mut parent = self.parent.as_deref();
+        while let Some(permit) = parent {
+            total += permit.permits;
+            parent = permit.parent.as_deref();
+        }
+        total
+    }
+
+    /// Drops the guard without returning its permits, lowering the
+    /// semaphore's capacity by [`PermitRef::num_permits`].
+    ///
+    /// For a child semaphore, the parent's capacity is lowered as well.
+    pub fn forget(mut self) {
+        self.root
+            .forget_held(self.permits, self.band.take().as_deref());
+        self.permits = 0;
+        if let Some(parent) = self.parent.take() {
+            parent.forget();
+        }
     }
 }
 
-impl Drop for Permit {
+impl<P: Ord> Drop for PermitRef<'_, P> {
     fn drop(&mut self) {
-        self.root.release_one();
+        if self.permits != 0 {
+            self.root.release_from(self.permits, self.band.as_deref());
+        }
     }
 }
@@ -0,0 +1,386 @@
+//! Policies that control how queued waiters are granted permits.
+
+use crate::semaphore::Priority;
+#[cfg(feature = "std")]
+use core::time::Duration;
+
+/// What happens when the highest-priority waiter needs more permits than are
+/// currently pooled for the queue.
+///
+/// Only multi-permit requests from
+/// [`PrioritySemaphore::acquire_many`](crate::PrioritySemaphore::acquire_many)
+/// can be blocked this way; single-permit waiters always fit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeadOfLine {
+    /// Every waiter waits behind the head until the head's whole request is
+    /// granted. This is the default and never starves the head.
+    #[default]
+    Strict,
+    /// Smaller, lower-priority requests may use permits that the head cannot
+    /// use yet. Among the requests that fit, the highest priority wins.
+    ///
+    /// A large request can starve if smaller ones keep arriving.
+    SkipAhead,
+    /// Like [`HeadOfLine::SkipAhead`], but only while the head has been
+    /// queued for less than the given duration. After that, the queue behaves
+    /// like [`HeadOfLine::Strict`] until the head is granted or cancelled.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    BoundedSkipAhead(Duration),
+}
+
+/// Opt-in aging that keeps low-priority waiters from starving.
+///
+/// While a waiter is queued, its effective priority rises by one for every
+/// completed interval it has waited, up to `cap` above its own priority. The
+/// queue is ordered by effective priority, with ties still served in arrival
+/// order.
+///
+/// Grant-based intervals are deterministic, which makes them the better
+/// choice for tests. Enabling aging makes every grant re-order the queue in
+/// O(n), rather than O(log n).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Aging {
+    interval: AgingInterval,
+    cap: Priority,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AgingInterval {
+    Grants(u64),
+    #[cfg(feature = "std")]
+    Elapsed(Duration),
+}
+
+impl Aging {
+    /// Raises a waiter by one for every `grants` grants made to other waiters
+    /// while it is queued, by at most `cap`. A grant counts once however many
+    /// permits it hands over.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `grants` is zero or `cap` is negative.
+    pub const fn per_grants(grants: u64, cap: Priority) -> Self {
+        assert!(grants > 0, "aging interval must not be zero");
+        assert!(cap >= 0, "aging cap must not be negative");
+        Self {
+            interval: AgingInterval::Grants(grants),
+            cap,
+        }
+    }
+
+    /// Raises a waiter by one for every `interval` it has been queued, by at
+    /// most `cap`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `interval` is zero or `cap` is negative.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub const fn per_duration(interval: Duration, cap: Priority) -> Self {
+        assert!(!interval.is_zero(), "aging interval must not be zero");
+        assert!(cap >= 0, "aging cap must not be negative");
+        Self {
+            interval: AgingInterval::Elapsed(interval),
+            cap,
+        }
+    }
+
+    /// Returns the largest boost a waiter can receive.
+    pub const fn cap(&self) -> Priority {
+        self.cap
+    }
+
+    pub(crate) fn is_time_based(&self) -> bool {
+        !matches!(self.interval, AgingInterval::Grants(_))
+    }
+
+    /// Boost for a waiter that has seen `grants` grants and, with `std`,
+    /// waited for `waited`.
+    pub(crate) fn boost(&self, grants: u64, #[cfg(feature = "std")] waited: Duration) -> Priority {
+        let steps = match self.interval {
+            AgingInterval::Grants(interval) => u128::from(grants / interval),
+            #[cfg(feature = "std")]
+            AgingInterval::Elapsed(interval) => waited.as_nanos() / interval.as_nanos(),
+        };
+        // The cap is non-negative, so the minimum always fits a Priority.
+        steps.min(self.cap as u128) as Priority
+    }
+}
+
+/// A condition under which an [`AdmissionRule`] turns acquisitions away.
+///
+/// It is also the reason carried by
+/// [`AcquireError::Rejected`](crate::AcquireError::Rejected) and
+/// [`TryAcquireError::Rejected`](crate::TryAcquireError::Rejected).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Busy {
+    /// More than this many acquisitions are queued.
+    QueueDeeperThan(usize),
+    /// No permits are available.
+    NoPermits,
+}
+
+impl core::fmt::Display for Busy {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        match self {
+            Busy::QueueDeeperThan(depth) => write!(f, "more than {depth} acquisitions queued"),
+            Busy::NoPermits => write!(f, "no permits available"),
+        }
+    }
+}
+
+/// Rejects acquisitions below a priority while the semaphore is [`Busy`].
+///
+/// For example, `AdmissionRule::new(0, Busy::QueueDeeperThan(100))` rejects
+/// negative priorities while more than 100 acquisitions are queued. Install
+/// rules with
+/// [`PrioritySemaphore::set_admission_rules`](crate::PrioritySemaphore::set_admission_rules).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdmissionRule<P = Priority> {
+    below: P,
+    when: Busy,
+}
+
+impl<P> AdmissionRule<P> {
+    /// Rejects priorities lower than `below` whenever `when` holds.
+    pub const fn new(below: P, when: Busy) -> Self {
+        Self { below, when }
+    }
+
+    /// Priorities lower than this are rejected.
+    pub const fn below(&self) -> &P {
+        &self.below
+    }
+
+    /// The condition under which the rule applies.
+    pub const fn when(&self) -> Busy {
+        self.when
+    }
+}
+
+impl<P: Ord> AdmissionRule<P> {
+    /// Returns the reason to reject `priority`, if this rule applies.
+    pub(crate) fn check(&self, priority: &P, queued: usize, available: usize) -> Option<Busy> {
+        let busy = match self.when {
+            Busy::QueueDeeperThan(depth) => queued > depth,
+            Busy::NoPermits => available == 0,
+        };
+        (busy && *priority < self.below).then_some(self.when)
+    }
+}
+
+/// Permits held back for a priority band.
+///
+/// Acquisitions below `at_least` can never take the last `permits` available
+/// permits, so the band can always get that many even when lower priorities
+/// have taken everything else. Bands are nested: a priority below several
+/// bands leaves all of their reservations untouched.
+///
+/// Install reservations with
+/// [`PrioritySemaphore::set_reservations`](crate::PrioritySemaphore::set_reservations).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reservation<P = Priority> {
+    at_least: P,
+    permits: usize,
+}
+
+impl<P> Reservation<P> {
+    /// Reserves `permits` for priorities greater than or equal to `at_least`.
+    pub const fn new(at_least: P, permits: usize) -> Self {
+        Self { at_least, permits }
+    }
+
+    /// Lowest priority in the band.
+    pub const fn at_least(&self) -> &P {
+        &self.at_least
+    }
+
+    /// Number of reserved permits.
+    pub const fn permits(&self) -> usize {
+        self.permits
+    }
+}
+
+impl<P: Ord> Reservation<P> {
+    /// Permits that `priority` must leave in the pool for this band.
+    pub(crate) fn withheld_from(&self, priority: &P) -> usize {
+        if *priority < self.at_least {
+            self.permits
+        } else {
+            0
+        }
+    }
+}
+
+/// Upper bound on the permits a low-priority band may hold at once.
+///
+/// Acquisitions below `below` are not granted while the band's permits
+/// already held would exceed `permits`. Queued waiters in a full band are
+/// passed over in favour of the next eligible waiter, so a full band never
+/// blocks the rest of the queue, or a `try_acquire` while no other waiter is
+/// short of returned permits. Caps do not nest: a priority counts toward the
+/// cap with the lowest `below` bound above it.
+///
+/// Install caps with
+/// [`PrioritySemaphore::set_caps`](crate::PrioritySemaphore::set_caps).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cap<P = Priority> {
+    below: P,
+    permits: usize,
+}
+
+impl<P> Cap<P> {
+    /// Lets priorities below `below` hold at most `permits` permits.
+    pub const fn new(below: P, permits: usize) -> Self {
+        Self { below, permits }
+    }
+
+    /// Priorities below this bound are in the band.
+    pub const fn below(&self) -> &P {
+        &self.below
+    }
+
+    /// Most permits the band may hold.
+    pub const fn permits(&self) -> usize {
+        self.permits
+    }
+
+    pub(crate) fn into_parts(self) -> (P, usize) {
+        (self.below, self.permits)
+    }
+}
+
+/// What a [`SchedulingPolicy`] can see of a queued waiter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaiterInfo<'a, P = Priority> {
+    priority: &'a P,
+    arrival: u64,
+    permits: usize,
+}
+
+impl<'a, P> WaiterInfo<'a, P> {
+    pub(crate) const fn new(priority: &'a P, arrival: u64, permits: usize) -> Self {
+        Self {
+            priority,
+            arrival,
+            permits,
+        }
+    }
+
+    /// The waiter's priority, including any [`Aging`] boost.
+    pub const fn priority(&self) -> &'a P {
+        self.priority
+    }
+
+    /// Arrival order. Later waiters have larger values.
+    pub const fn arrival(&self) -> u64 {
+        self.arrival
+    }
+
+    /// Number of permits the waiter requested.
+    pub const fn permits(&self) -> usize {
+        self.permits
+    }
+}
+
+/// Decides which queued waiter is granted the next permits.
+///
+/// The queue is a binary heap ordered by [`SchedulingPolicy::outranks`], so
+/// granting, queueing and cancelling stay O(log n) for any policy whose order
+/// only changes when [`SchedulingPolicy::on_grant`] says so. `P` is the
+/// semaphore's priority type.
+///
+/// Install a policy with
+/// [`PrioritySemaphore::set_scheduling_policy`](crate::PrioritySemaphore::set_scheduling_policy).
+pub trait SchedulingPolicy<P = Priority>: core::fmt::Debug + Send {
+    /// Returns `true` if `waiter` should be granted before `other`.
+    ///
+    /// This must be a strict total order over the waiters currently queued.
+    /// Arrival order is unique, so it is a suitable final tie-breaker.
+    fn outranks(&self, waiter: &WaiterInfo<'_, P>, other: &WaiterInfo<'_, P>) -> bool;
+
+    /// Called after `granted` has been handed its permits.
+    ///
+    /// Returns `true` if this changed the order of the waiters that are still
+    /// queued. The queue is then re-ordered in O(n).
+    fn on_grant(&mut self, granted: &WaiterInfo<'_, P>) -> bool {
+        let _ = granted;
+        false
+    }
+}
+
+/// The default policy: highest priority first, equal priorities in arrival
+/// order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PriorityFifo;
+
+impl<P: Ord> SchedulingPolicy<P> for PriorityFifo {
+    fn outranks(&self, waiter: &WaiterInfo<'_, P>, other: &WaiterInfo<'_, P>) -> bool {
+        (waiter.priority, other.arrival) > (other.priority, waiter.arrival)
+    }
+}
+
+/// Highest priority first, equal priorities newest first.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct PriorityLifo;
+
+impl<P: Ord> SchedulingPolicy<P> for PriorityLifo {
+    fn outranks(&self, waiter: &WaiterInfo<'_, P>, other: &WaiterInfo<'_, P>) -> bool {
+        (waiter.priority, waiter.arrival) > (other.priority, other.arrival)
+    }
+}
+
+/// Earliest deadline first: the priority is read as a deadline, so the
+/// *smallest* value is granted first. Equal deadlines are served in arrival
+/// order.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EarliestDeadlineFirst;
+
+impl<P: Ord> SchedulingPolicy<P> for EarliestDeadlineFirst {
+    fn outranks(&self, waiter: &WaiterInfo<'_, P>, other: &WaiterInfo<'_, P>) -> bool {
+        (waiter.priority, waiter.arrival) < (other.priority, other.arrival)
+    }
+}
+
+/// Round-robin across priority classes.
+///
+/// Every distinct priority is a class. Classes take turns from the highest
+/// to the lowest and then wrap around, so each class with queued waiters is
+/// granted once per round. Within a class, waiters are served in arrival
+/// order. Every grant re-orders the queue in O(n).
+#[derive(Debug, Clone, Copy)]
+pub struct RoundRobin<P = Priority> {
+    last: Option<P>,
+}
+
+impl<P> Default for RoundRobin<P> {
+    fn default() -> Self {
+        Self { last: None }
+    }
+}
+
+impl<P: Ord> RoundRobin<P> {
+    /// Whether `priority` still has its turn in the current round.
+    fn pending(&self, priority: &P) -> bool {
+        self.last.as_ref().is_none_or(|last| priority < last)
+    }
+}
+
+impl<P> SchedulingPolicy<P> for RoundRobin<P>
+where
+    P: Ord + Clone + core::fmt::Debug + Send,
+{
+    fn outranks(&self, waiter: &WaiterInfo<'_, P>, other: &WaiterInfo<'_, P>) -> bool {
+        (
+            self.pending(waiter.priority),
+            waiter.priority,
+            other.arrival,
+        ) > (self.pending(other.priority), other.priority, waiter.arrival)
+    }
+
+    fn on_grant(&mut self, granted: &WaiterInfo<'_, P>) -> bool {
+        self.last = Some(granted.priority.clone());
+        true
+    }
+}
@@ -1,13 +1,32 @@
 //! Indexed, stable priority queue used by contended acquisitions.
 
-use crate::{semaphore::Priority, waiter::Waiter};
-use alloc::{sync::Arc, vec::Vec};
-use core::{cmp::Ordering, task::Waker};
+use crate::{
+    error::CloseReason,
+    permit::{Band, Revocation},
+    policy::{
+        AdmissionRule, Aging, Busy, Cap, HeadOfLine, Reservation, SchedulingPolicy, WaiterInfo,
+    },
+    semaphore::Priority,
+    waiter::{Waiter, WakeList},
+};
+use alloc::{
+    boxed::Box,
+    collections::{BTreeMap, BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{cmp::Ordering, ops::Deref, task::Waker};
+#[cfg(feature = "std")]
+use std::time::Instant;
 
 const VACANT: usize = usize::MAX;
 
+/// Virtual time a tenant is charged per permit at weight 1. Large enough
+/// that dividing it by any `u32` weight still leaves a distinct cost.
+const WEIGHT_SCALE: u128 = 1 << 32;
+
 /// Stable handle held by an acquire future while it is queued.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub(crate) struct WaitKey {
     slot: usize,
     generation: usize,
@@ -17,25 +36,65 @@ pub(crate) struct WaitKey {
 struct Slot {
     generation: usize,
     heap_index: usize,
+    lowest_index: usize,
+    tenant: usize,
+    tenant_index: usize,
     next_free: usize,
 }
 
+/// What an acquisition is queued under besides its priority.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Labels {
+    // Tenant and weight for a fair-shared acquisition.
+    pub(crate) tenant: Option<(u64, u32)>,
+    // Tag matched by selective cancellation.
+    pub(crate) tag: Option<u64>,
+}
+
 /// A queued waiter. Older waiters win ties at the same priority.
 #[derive(Debug)]
-pub(crate) struct WaiterEntry {
-    priority: Priority,
+pub(crate) struct WaiterEntry<P> {
+    priority: P,
+    permits: usize,
     sequence: u64,
-    key: WaitKey,
+    enqueued_grants: u64,
+    #[cfg(feature = "std")]
+    enqueued_at: Instant,
+    #[cfg(feature = "std")]
+    pub(crate) deadline: Option<Instant>,
+    tag: Option<u64>,
+    pub(crate) key: WaitKey,
     pub(crate) waiter: Arc<Waiter>,
     pub(crate) waker: Waker,
 }
 
-impl WaiterEntry {
-    fn outranks(&self, other: &Self) -> bool {
-        self.priority
-            .cmp(&other.priority)
-            .then_with(|| other.sequence.cmp(&self.sequence))
-            == Ordering::Greater
+/// A granted preemptible permit that a higher-priority waiter can revoke.
+#[derive(Debug)]
+struct Holder<P> {
+    priority: P,
+    permits: usize,
+    revocation: Arc<Revocation>,
+    // The waiter this holder was revoked for.
+    revoked_for: Option<WaitKey>,
+}
+
+/// Raises a priority by an [`Aging`] boost.
+type Boost<P> = fn(&P, Priority) -> P;
+
+/// A waiter's priority as the queue currently ranks it.
+enum Effective<'a, P> {
+    Own(&'a P),
+    Aged(P),
+}
+
+impl<P> Deref for Effective<'_, P> {
+    type Target = P;
+
+    fn deref(&self) -> &P {
+        match self {
+            Self::Own(priority) => priority,
+            Self::Aged(priority) => priority,
+        }
     }
 }
 
@@ -43,27 +102,454 @@ impl WaiterEntry {
 ///
 /// The slot table makes cancellation and waker replacement O(log n) and O(1)
 /// respectively, instead of scanning every queued waiter.
+///
+/// With [`Aging`] enabled the heap is ordered by effective priority as of the
+/// last grant (or, for time-based aging, the last refresh), and is rebuilt
+/// whenever that point moves. Without a custom [`SchedulingPolicy`], the
+/// built-in priority/FIFO order is used directly rather than through a
+/// trait object.
+///
+/// A bounded queue also keeps a min-heap of slots, so the lowest-ranked
+/// waiter can be evicted in O(log n). Unbounded queues do not pay for it.
+/// Likewise, the [`FairQueue`] only exists while tenants are waiting.
 #[derive(Debug)]
-pub(crate) struct WaitQueue {
-    heap: Vec<WaiterEntry>,
+pub(crate) struct WaitQueue<P> {
+    heap: Vec<WaiterEntry<P>>,
     slots: Vec<Slot>,
     free_head: usize,
     next_sequence: u64,
+    head_of_line: HeadOfLine,
+    // Aging is arithmetic on the priority, so it carries its own boost.
+    aging: Option<(Aging, Boost<P>)>,
+    policy: Option<Box<dyn SchedulingPolicy<P>>>,
+    grants: u64,
+    #[cfg(feature = "std")]
+    aged_at: Option<Instant>,
+    max_len: Option<usize>,
+    admission: Vec<AdmissionRule<P>>,
+    reservations: Vec<Reservation<P>>,
+    // Capped bands in ascending order of their bound.
+    caps: Vec<(P, Arc<Band>)>,
+    // Slots ordered lowest rank first. Only maintained while bounded.
+    lowest: Vec<usize>,
+    fair: Option<FairQueue>,
+    // Preemptible permits still held, oldest first.
+    holders: Vec<Holder<P>>,
+    // How far a waiter must rank above a holder to revoke it.
+    preemption_margin: Option<(Priority, Boost<P>)>,
+    close_reason: Option<CloseReason>,
+    // `closed_and_idle` futures waiting for the semaphore to become idle.
+    idle: Vec<(u64, Waker)>,
+    next_idle: u64,
+}
+
+/// Weighted fair sharing across tenants, kept next to the heap while any
+/// waiter queued by tenant is waiting.
+///
+/// Every tenant has a virtual time that advances by `permits / weight` for
+/// each grant, and the tenant furthest behind is served next (start-time
+/// fair queuing). Within a tenant, waiters keep their rank through a heap of
+/// slots per tenant. Waiters queued without a tenant share the plain tenant
+/// at weight 1. The whole structure is dropped when the queue empties, so
+/// only tenants that wait at the same time compete with each other.
+#[derive(Debug)]
+struct FairQueue {
+    // Virtual time of the latest grant. A tenant that starts waiting joins
+    // here rather than cashing in the time it spent idle.
+    vtime: u128,
+    ids: BTreeMap<Option<u64>, usize>,
+    tenants: Vec<Tenant>,
+    // Tenants with queued waiters, furthest behind first.
+    backlog: BTreeSet<(u128, usize)>,
+}
+
+#[derive(Debug)]
+struct Tenant {
+    weight: u32,
+    vtime: u128,
+    // Slots of the tenant's waiters, highest rank first.
+    heap: Vec<usize>,
 }
 
-impl WaitQueue {
+impl FairQueue {
+    const fn new() -> Self {
+        Self {
+            vtime: 0,
+            ids: BTreeMap::new(),
+            tenants: Vec::new(),
+            backlog: BTreeSet::new(),
+        }
+    }
+
+    /// Returns the index of tenant `id`, adding it if it is new.
+    fn tenant(&mut self, id: Option<u64>, weight: u32) -> usize {
+        let tenants = &mut self.tenants;
+        let index = *self.ids.entry(id).or_insert_with(|| {
+            tenants.push(Tenant {
+                weight,
+                vtime: 0,
+                heap: Vec::new(),
+            });
+            tenants.len() - 1
+        });
+        self.tenants[index].weight = weight;
+        index
+    }
+
+    /// Marks `tenant` as waiting, joining at the current virtual time.
+    fn activate(&mut self, tenant: usize) {
+        let state = &mut self.tenants[tenant];
+        state.vtime = state.vtime.max(self.vtime);
+        self.backlog.insert((state.vtime, tenant));
+    }
+
+    fn deactivate(&mut self, tenant: usize) {
+        self.backlog.remove(&(self.tenants[tenant].vtime, tenant));
+    }
+
+    /// Advances `tenant`'s virtual time for a grant of `permits`.
+    fn charge(&mut self, tenant: usize, permits: usize) {
+        let state = &mut self.tenants[tenant];
+        let waiting = self.backlog.remove(&(state.vtime, tenant));
+        self.vtime = self.vtime.max(state.vtime);
+        state.vtime += permits as u128 * WEIGHT_SCALE / u128::from(state.weight);
+        if waiting {
+            self.backlog.insert((state.vtime, tenant));
+        }
+    }
+
+    /// Returns the waiting tenant that is furthest behind.
+    fn first(&self) -> Option<usize> {
+        self.backlog.first().map(|&(_, tenant)| tenant)
+    }
+
+    /// Whether `tenant` is served before `other`.
+    fn ahead(&self, tenant: usize, other: usize) -> bool {
+        (self.tenants[tenant].vtime, tenant) < (self.tenants[other].vtime, other)
+    }
+}
+
+impl<P: Ord> WaitQueue<P> {
     pub(crate) const fn new() -> Self {
         Self {
             heap: Vec::new(),
             slots: Vec::new(),
             free_head: VACANT,
             next_sequence: 0,
+            head_of_line: HeadOfLine::Strict,
+            aging: None,
+            policy: None,
+            grants: 0,
+            #[cfg(feature = "std")]
+            aged_at: None,
+            max_len: None,
+            admission: Vec::new(),
+            reservations: Vec::new(),
+            caps: Vec::new(),
+            lowest: Vec::new(),
+            fair: None,
+            holders: Vec::new(),
+            preemption_margin: None,
+            close_reason: None,
+            idle: Vec::new(),
+            next_idle: 0,
+        }
+    }
+
+    /// Registers the waker of the `closed_and_idle` future `id`, giving the
+    /// future an id first if it has none yet.
+    pub(crate) fn add_idle_waker(&mut self, id: &mut Option<u64>, waker: &Waker) {
+        let id = *id.get_or_insert_with(|| {
+            let id = self.next_idle;
+            self.next_idle = self.next_idle.wrapping_add(1);
+            id
+        });
+        match self.idle.iter_mut().find(|(idle, _)| *idle == id) {
+            Some((_, current)) => current.clone_from(waker),
+            None => self.idle.push((id, waker.clone())),
         }
     }
 
+    pub(crate) fn remove_idle_waker(&mut self, id: u64) {
+        self.idle.retain(|(idle, _)| *idle != id);
+    }
+
+    pub(crate) fn has_idle_wakers(&self) -> bool {
+        !self.idle.is_empty()
+    }
+
+    pub(crate) fn take_idle_wakers(&mut self) -> Vec<Waker> {
+        core::mem::take(&mut self.idle)
+            .into_iter()
+            .map(|(_, waker)| waker)
+            .collect()
+    }
+
+    pub(crate) fn close_reason(&self) -> Option<&CloseReason> {
+        self.close_reason.as_ref()
+    }
+
+    pub(crate) fn set_close_reason(&mut self, reason: Option<CloseReason>) {
+        self.close_reason = reason;
+    }
+
+    pub(crate) fn admission(&self) -> &[AdmissionRule<P>] {
+        &self.admission
+    }
+
+    pub(crate) fn set_admission(&mut self, rules: Vec<AdmissionRule<P>>) {
+        self.admission = rules;
+    }
+
+    /// Returns the reason of the first admission rule that rejects
+    /// `priority`.
+    pub(crate) fn reject(&self, priority: &P, available: usize) -> Option<Busy> {
+        let queued = self.heap.len();
+        self.admission
+            .iter()
+            .find_map(|rule| rule.check(priority, queued, available))
+    }
+
+    pub(crate) fn reservations(&self) -> &[Reservation<P>] {
+        &self.reservations
+    }
+
+    /// Replaces the reservations and returns the total number of reserved
+    /// permits.
+    pub(crate) fn set_reservations(&mut self, reservations: Vec<Reservation<P>>) -> usize {
+        self.reservations = reservations;
+        self.reservations
+            .iter()
+            .fold(0, |total, band| total.saturating_add(band.permits()))
+    }
+
+    /// Permits that `priority` must leave in the pool for higher bands.
+    pub(crate) fn headroom(&self, priority: &P) -> usize {
+        self.reservations.iter().fold(0, |total, band| {
+            total.saturating_add(band.withheld_from(priority))
+        })
+    }
+
+    /// [`WaitQueue::headroom`] for the waiter at `index`.
+    pub(crate) fn headroom_at(&self, index: usize) -> usize {
+        self.headroom(&self.heap[index].priority)
+    }
+
+    pub(crate) fn caps(&self) -> Vec<Cap<P>>
+    where
+        P: Clone,
+    {
+        self.caps
+            .iter()
+            .map(|(below, band)| Cap::new(below.clone(), band.limit()))
+            .collect()
+    }
+
+    /// Replaces the caps and returns whether any are installed.
+    ///
+    /// A band whose bound is unchanged keeps counting the permits it already
+    /// holds. Permits held in a band that is removed keep discharging into
+    /// it, but it no longer limits anything.
+    pub(crate) fn set_caps(&mut self, caps: Vec<Cap<P>>) -> bool {
+        let mut previous = core::mem::take(&mut self.caps);
+        self.caps = caps
+            .into_iter()
+            .map(|cap| {
+                let (below, limit) = cap.into_parts();
+                match previous.iter().position(|(bound, _)| *bound == below) {
+                    Some(position) => {
+                        let (_, band) = previous.swap_remove(position);
+                        band.set_limit(limit);
+                        (below, band)
+                    }
+                    None => (below, Arc::new(Band::new(limit))),
+                }
+            })
+            .collect();
+        self.caps.sort_by(|(a, _), (b, _)| a.cmp(b));
+        !self.caps.is_empty()
+    }
+
+    /// Returns the capped band that `priority` counts toward.
+    pub(crate) fn band(&self, priority: &P) -> Option<&Arc<Band>> {
+        self.caps
+            .iter()
+            .find(|(below, _)| priority < below)
+            .map(|(_, band)| band)
+    }
+
+    /// Whether a request arriving now may be granted ahead of the queue.
+    ///
+    /// It may only when every queued waiter, whatever its rank, is held
+    /// back by a [`Reservation`] or a full [`Cap`] rather than waiting for
+    /// permits to be returned; otherwise the arrival could take permits
+    /// pooled for that waiter.
+    pub(crate) fn only_held_back(&self, available: usize) -> bool {
+        self.heap
+            .iter()
+            .all(|entry| self.held_back(entry, available))
+    }
+
+    /// Whether the head-of-line policy lets smaller requests skip ahead of
+    /// `head` while it waits for permits to be returned.
+    #[cfg_attr(not(feature = "std"), allow(unused_variables))]
+    fn may_skip(&self, head: &WaiterEntry<P>) -> bool {
+        match self.head_of_line {
+            HeadOfLine::Strict => false,
+            HeadOfLine::SkipAhead => true,
+            #[cfg(feature = "std")]
+            HeadOfLine::BoundedSkipAhead(limit) => head.enqueued_at.elapsed() < limit,
+        }
+    }
+
+    /// Whether a waiter arriving now may use permits that are already
+    /// available: the queue either holds only waiters that
+    /// [`WaitQueue::only_held_back`] passes over, or its head-of-line policy
+    /// lets smaller requests skip ahead of its head.
+    pub(crate) fn admits_arrival(&self, available: usize) -> bool {
+        self.only_held_back(available)
+            || self
+                .head_index()
+                .is_some_and(|index| self.may_skip(&self.heap[index]))
+    }
+
+    /// Whether `entry` is not granted because its capped band is full, or
+    /// because of the permits it must leave to higher bands, although
+    /// enough permits are available.
+    fn held_back(&self, entry: &WaiterEntry<P>, available: usize) -> bool {
+        entry.permits <= available
+            && (self
+                .band(&entry.priority)
+                .is_some_and(|band| !band.has_room(entry.permits))
+                || entry.permits.saturating_add(self.headroom(&entry.priority)) > available)
+    }
+
+    /// [`WaitQueue::band`] for the waiter at `index`.
+    pub(crate) fn band_at(&self, index: usize) -> Option<&Arc<Band>> {
+        self.band(&self.heap[index].priority)
+    }
+
+    pub(crate) fn add_holder(&mut self, priority: P, permits: usize, revocation: Arc<Revocation>) {
+        self.holders.push(Holder {
+            priority,
+            permits,
+            revocation,
+            revoked_for: None,
+        });
+    }
+
+    pub(crate) fn remove_holder(&mut self, revocation: &Arc<Revocation>) {
+        if let Some(index) = self
+            .holders
+            .iter()
+            .position(|holder| Arc::ptr_eq(&holder.revocation, revocation))
+        {
+            self.holders.remove(index);
+        }
+    }
+
+    pub(crate) fn preemption_margin(&self) -> Priority {
+        self.preemption_margin.map_or(0, |(margin, _)| margin)
+    }
+
+    /// Revokes the lowest-priority preemptible holders more than the margin
+    /// below the waiter behind `key` until they cover its request.
+    ///
+    /// Holders already revoked for this waiter and not yet returned count
+    /// toward its request. Among holders of the same priority the newest
+    /// goes first, since it has the least work to lose.
+    pub(crate) fn preempt(&mut self, key: WaitKey, wake: &mut WakeList) {
+        let Some(index) = self.index_of(key) else {
+            return;
+        };
+        let entry = &self.heap[index];
+        let margin = self.preemption_margin;
+        let mut covered: usize = self
+            .holders
+            .iter()
+            .filter(|holder| holder.revoked_for == Some(key))
+            .map(|holder| holder.permits)
+            .sum();
+        while covered < entry.permits {
+            let Some(holder) = self
+                .holders
+                .iter_mut()
+                .rev()
+                .filter(|holder| !holder.revocation.is_revoked())
+                .filter(|holder| match margin {
+                    Some((margin, raise)) => raise(&holder.priority, margin) < entry.priority,
+                    None => holder.priority < entry.priority,
+                })
+                .min_by(|a, b| a.priority.cmp(&b.priority))
+            else {
+                return;
+            };
+            covered += holder.permits;
+            holder.revoked_for = Some(key);
+            if let Some(waker) = holder.revocation.revoke() {
+                wake.push(waker);
+            }
+        }
+    }
+
+    pub(crate) fn max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    pub(crate) fn set_max_len(&mut self, max_len: Option<usize>) {
+        let was_bounded = self.max_len.is_some();
+        self.max_len = max_len;
+        if max_len.is_none() {
+            self.lowest = Vec::new();
+        } else if !was_bounded {
+            self.lowest = self.heap.iter().map(|entry| entry.key.slot).collect();
+            for (index, &slot) in self.lowest.iter().enumerate() {
+                self.slots[slot].lowest_index = index;
+            }
+            self.rebuild_lowest();
+        }
+    }
+
+    /// Removes the lowest-ranked waiter while the queue is over its bound.
+    pub(crate) fn pop_overflow(&mut self) -> Option<WaiterEntry<P>> {
+        let max_len = self.max_len?;
+        if self.heap.len() <= max_len {
+            return None;
+        }
+        let index = self.slots[self.lowest[0]].heap_index;
+        Some(self.remove_at(index))
+    }
+
+    pub(crate) fn aging(&self) -> Option<Aging> {
+        self.aging.map(|(aging, _)| aging)
+    }
+
+    pub(crate) fn set_policy(&mut self, policy: Box<dyn SchedulingPolicy<P>>) {
+        self.policy = Some(policy);
+        self.rebuild();
+    }
+
+    /// Moves the clock of time-based aging to now and re-orders the queue.
+    pub(crate) fn refresh_aging(&mut self) {
+        #[cfg(feature = "std")]
+        if self.aging.is_some_and(|(aging, _)| aging.is_time_based()) {
+            self.aged_at = Some(Instant::now());
+            self.rebuild();
+        }
+    }
+
+    pub(crate) fn head_of_line(&self) -> HeadOfLine {
+        self.head_of_line
+    }
+
+    pub(crate) fn set_head_of_line(&mut self, policy: HeadOfLine) {
+        self.head_of_line = policy;
+    }
+
     pub(crate) fn push(
         &mut self,
-        priority: Priority,
+        priority: P,
+        permits: usize,
         waiter: Arc<Waiter>,
         waker: Waker,
     ) -> WaitKey {
@@ -73,25 +559,179 @@ impl WaitQueue {
         let index = self.heap.len();
         self.heap.push(WaiterEntry {
             priority,
+            permits,
             sequence,
+            enqueued_grants: self.grants,
+            #[cfg(feature = "std")]
+            enqueued_at: Instant::now(),
+            #[cfg(feature = "std")]
+            deadline: None,
+            tag: None,
             key,
             waiter,
             waker,
         });
         self.slots[key.slot].heap_index = index;
         self.sift_up(index);
+        if self.max_len.is_some() {
+            let lowest_index = self.lowest.len();
+            self.lowest.push(key.slot);
+            self.slots[key.slot].lowest_index = lowest_index;
+            self.sift_up_lowest(lowest_index);
+        }
+        if self.fair.is_some() {
+            let plain = self.fair_mut().tenant(None, 1);
+            self.join_tenant(key.slot, plain);
+        }
         key
     }
 
-    pub(crate) fn pop(&mut self) -> Option<WaiterEntry> {
+    /// Queues the waiter under `key` as part of `tenant`'s fair share.
+    ///
+    /// The first tenant starts fair sharing, with every waiter queued so far
+    /// placed in the plain tenant.
+    pub(crate) fn set_tenant(&mut self, key: WaitKey, tenant: u64, weight: u32) {
+        if self.index_of(key).is_none() {
+            return;
+        }
+        if self.fair.is_none() {
+            let mut fair = FairQueue::new();
+            let plain = fair.tenant(None, 1);
+            self.fair = Some(fair);
+            for index in 0..self.heap.len() {
+                self.join_tenant(self.heap[index].key.slot, plain);
+            }
+        }
+        self.leave_tenant(key.slot);
+        let tenant = self.fair_mut().tenant(Some(tenant), weight);
+        self.join_tenant(key.slot, tenant);
+    }
+
+    /// Chooses the waiter that `available` pooled permits should go to.
+    ///
+    /// Returns its heap index and request size. The head, or with tenants
+    /// the best waiter of the tenant furthest behind, is chosen whenever it
+    /// fits. Otherwise the head-of-line policy decides whether a smaller
+    /// request may go first, which costs a linear scan of the queue. A head
+    /// that is only held back by a [`Reservation`] or a full [`Cap`] never
+    /// blocks the waiters that may still be granted.
+    pub(crate) fn select(&self, available: usize) -> Option<(usize, usize)> {
+        let fits = |entry: &WaiterEntry<P>| {
+            entry.permits.saturating_add(self.headroom(&entry.priority)) <= available
+                && self
+                    .band(&entry.priority)
+                    .is_none_or(|band| band.has_room(entry.permits))
+        };
+        let head_index = self.head_index()?;
+        let head = &self.heap[head_index];
+        if fits(head) {
+            return Some((head_index, head.permits));
+        }
+        if head.permits > available && !self.may_skip(head) {
+            return None;
+        }
+        let mut best: Option<usize> = None;
+        for (index, entry) in self.heap.iter().enumerate() {
+            if index != head_index
+                && fits(entry)
+                && best.is_none_or(|best| self.goes_before(entry, &self.heap[best]))
+            {
+                best = Some(index);
+            }
+        }
+        best.map(|index| (index, self.heap[index].permits))
+    }
+
+    /// The waiter that [`WaitQueue::select`] serves first.
+    fn head_index(&self) -> Option<usize> {
+        let index = match &self.fair {
+            Some(fair) => {
+                let slot = fair.tenants[fair.first()?].heap[0];
+                self.slots[slot].heap_index
+            }
+            None => 0,
+        };
+        (index < self.heap.len()).then_some(index)
+    }
+
+    #[cfg(test)]
+    pub(crate) fn pop(&mut self) -> Option<WaiterEntry<P>> {
         (!self.heap.is_empty()).then(|| self.remove_at(0))
     }
 
-    pub(crate) fn remove(&mut self, key: WaitKey) -> Option<WaiterEntry> {
+    /// Removes a waiter that is being granted permits.
+    pub(crate) fn take(&mut self, index: usize) -> WaiterEntry<P> {
+        let tenant = self.slots[self.heap[index].key.slot].tenant;
+        let entry = self.remove_at(index);
+        if let Some(fair) = &mut self.fair {
+            fair.charge(tenant, entry.permits);
+        }
+        let priority = self.effective_priority(&entry);
+        let reordered = self.policy.as_mut().is_some_and(|policy| {
+            policy.on_grant(&WaiterInfo::new(&priority, entry.sequence, entry.permits))
+        });
+        self.grants = self.grants.wrapping_add(1);
+        if reordered || self.aging.is_some_and(|(aging, _)| !aging.is_time_based()) {
+            self.rebuild();
+        }
+        entry
+    }
+
+    /// Fails the waiter instead of granting it once `deadline` has passed.
+    #[cfg(feature = "std")]
+    pub(crate) fn set_deadline(&mut self, key: WaitKey, deadline: Instant) {
+        if let Some(index) = self.index_of(key) {
+            self.heap[index].deadline = Some(deadline);
+        }
+    }
+
+    /// Removes the selected waiter instead of granting it if its deadline
+    /// has passed.
+    #[cfg(feature = "std")]
+    pub(crate) fn expire(&mut self, index: usize) -> Option<WaiterEntry<P>> {
+        let expired = self.heap[index]
+            .deadline
+            .is_some_and(|deadline| deadline <= Instant::now());
+        expired.then(|| self.remove_at(index))
+    }
+
+    /// Removes the head waiter if its deadline has passed.
+    #[cfg(feature = "std")]
+    pub(crate) fn pop_expired(&mut self) -> Option<WaiterEntry<P>> {
+        if self.heap.is_empty() {
+            return None;
+        }
+        self.expire(0)
+    }
+
+    pub(crate) fn remove(&mut self, key: WaitKey) -> Option<WaiterEntry<P>> {
         let index = self.index_of(key)?;
         Some(self.remove_at(index))
     }
 
+    /// Files the waiter under `tag` for selective cancellation.
+    pub(crate) fn set_tag(&mut self, key: WaitKey, tag: u64) {
+        if let Some(index) = self.index_of(key) {
+            self.heap[index].tag = Some(tag);
+        }
+    }
+
+    /// Removes every waiter whose own priority and tag satisfy `matches`.
+    pub(crate) fn remove_where<F>(&mut self, mut matches: F) -> Vec<WaiterEntry<P>>
+    where
+        F: FnMut(&P, Option<u64>) -> bool,
+    {
+        let keys: Vec<_> = self
+            .heap
+            .iter()
+            .filter(|entry| matches(&entry.priority, entry.tag))
+            .map(|entry| entry.key)
+            .collect();
+        keys.into_iter()
+            .filter_map(|key| self.remove(key))
+            .collect()
+    }
+
     pub(crate) fn update_waker(&mut self, key: WaitKey, waker: &Waker) -> bool {
         let Some(index) = self.index_of(key) else {
             return false;
@@ -102,10 +742,32 @@ impl WaitQueue {
         true
     }
 
-    pub(crate) fn drain(&mut self) -> Vec<WaiterEntry> {
+    /// Changes a queued waiter's priority in place.
+    ///
+    /// The waiter keeps its original sequence number, so it is still ordered
+    /// by arrival among waiters of its new priority.
+    pub(crate) fn set_priority(&mut self, key: WaitKey, priority: P) -> bool {
+        let Some(index) = self.index_of(key) else {
+            return false;
+        };
+        self.heap[index].priority = priority;
+        self.repair(index);
+        if self.max_len.is_some() {
+            self.repair_lowest(self.slots[key.slot].lowest_index);
+        }
+        if self.fair.is_some() {
+            let slot = &self.slots[key.slot];
+            self.repair_tenant(slot.tenant, slot.tenant_index);
+        }
+        true
+    }
+
+    pub(crate) fn drain(&mut self) -> Vec<WaiterEntry<P>> {
         // Closing does not need priority order. Taking the heap directly keeps
         // mass wake-up O(n), rather than repeatedly repairing it in O(n log n).
         let entries = core::mem::take(&mut self.heap);
+        self.lowest.clear();
+        self.fair = None;
         for entry in &entries {
             self.vacate_slot(entry.key);
         }
@@ -126,6 +788,9 @@ impl WaitQueue {
             self.slots.push(Slot {
                 generation: 0,
                 heap_index: VACANT,
+                lowest_index: VACANT,
+                tenant: VACANT,
+                tenant_index: VACANT,
                 next_free: VACANT,
             });
             WaitKey {
@@ -143,27 +808,107 @@ impl WaitQueue {
         }
     }
 
+    /// Returns the priority a queued waiter was given, before aging.
+    pub(crate) fn priority_of(&self, key: WaitKey) -> Option<&P> {
+        self.index_of(key).map(|index| &self.heap[index].priority)
+    }
+
     fn index_of(&self, key: WaitKey) -> Option<usize> {
         let slot = self.slots.get(key.slot)?;
         (slot.generation == key.generation && slot.heap_index != VACANT).then_some(slot.heap_index)
     }
 
-    fn remove_at(&mut self, index: usize) -> WaiterEntry {
+    fn remove_at(&mut self, index: usize) -> WaiterEntry<P> {
+        if self.max_len.is_some() {
+            self.remove_lowest(self.heap[index].key.slot);
+        }
+        if self.fair.is_some() {
+            self.leave_tenant(self.heap[index].key.slot);
+        }
         let removed = self.heap.swap_remove(index);
         self.vacate_slot(removed.key);
 
         if index < self.heap.len() {
             let moved_key = self.heap[index].key;
             self.slots[moved_key.slot].heap_index = index;
-            if index > 0 && self.heap[index].outranks(&self.heap[(index - 1) / 2]) {
-                self.sift_up(index);
-            } else {
-                self.sift_down(index);
-            }
+            self.repair(index);
+        } else if self.heap.is_empty() {
+            self.fair = None;
         }
         removed
     }
 
+    /// Moves an entry whose rank changed to its place in the heap.
+    fn repair(&mut self, index: usize) {
+        if index > 0 && self.outranks(&self.heap[index], &self.heap[(index - 1) / 2]) {
+            self.sift_up(index);
+        } else {
+            self.sift_down(index);
+        }
+    }
+
+    fn effective_priority<'e>(&self, entry: &'e WaiterEntry<P>) -> Effective<'e, P> {
+        let Some((aging, raise)) = self.aging else {
+            return Effective::Own(&entry.priority);
+        };
+        let grants = self.grants.wrapping_sub(entry.enqueued_grants);
+        #[cfg(feature = "std")]
+        let waited = self
+            .aged_at
+            .map(|now| now.saturating_duration_since(entry.enqueued_at))
+            .unwrap_or_default();
+        let boost = aging.boost(
+            grants,
+            #[cfg(feature = "std")]
+            waited,
+        );
+        Effective::Aged(raise(&entry.priority, boost))
+    }
+
+    fn outranks(&self, entry: &WaiterEntry<P>, other: &WaiterEntry<P>) -> bool {
+        let priority = self.effective_priority(entry);
+        let other_priority = self.effective_priority(other);
+        match &self.policy {
+            Some(policy) => policy.outranks(
+                &WaiterInfo::new(&priority, entry.sequence, entry.permits),
+                &WaiterInfo::new(&other_priority, other.sequence, other.permits),
+            ),
+            None => {
+                (*priority)
+                    .cmp(&other_priority)
+                    .then_with(|| other.sequence.cmp(&entry.sequence))
+                    == Ordering::Greater
+            }
+        }
+    }
+
+    /// Grant order: fair share across tenants first, then rank.
+    fn goes_before(&self, entry: &WaiterEntry<P>, other: &WaiterEntry<P>) -> bool {
+        if let Some(fair) = &self.fair {
+            let tenant = self.slots[entry.key.slot].tenant;
+            let other_tenant = self.slots[other.key.slot].tenant;
+            if tenant != other_tenant {
+                return fair.ahead(tenant, other_tenant);
+            }
+        }
+        self.outranks(entry, other)
+    }
+
+    /// Restores the heap property after effective priorities changed.
+    fn rebuild(&mut self) {
+        for index in (0..self.heap.len() / 2).rev() {
+            self.sift_down(index);
+        }
+        if self.max_len.is_some() {
+            self.rebuild_lowest();
+        }
+        if let Some(fair) = &self.fair {
+            for tenant in 0..fair.tenants.len() {
+                self.rebuild_tenant(tenant);
+            }
+        }
+    }
+
     fn vacate_slot(&mut self, key: WaitKey) {
         let slot = &mut self.slots[key.slot];
         slot.generation = slot.generation.wrapping_add(1);
@@ -181,7 +926,7 @@ impl WaitQueue {
     fn sift_up(&mut self, mut index: usize) {
         while index > 0 {
             let parent = (index - 1) / 2;
-            if !self.heap[index].outranks(&self.heap[parent]) {
+            if !self.outranks(&self.heap[index], &self.heap[parent]) {
                 break;
             }
             self.swap(index, parent);
@@ -196,20 +941,199 @@ impl WaitQueue {
                 return;
             }
             let right = left + 1;
-            let best = if right < self.heap.len() && self.heap[right].outranks(&self.heap[left]) {
+            let best =
+                if right < self.heap.len() && self.outranks(&self.heap[right], &self.heap[left]) {
+                    right
+                } else {
+                    left
+                };
+            if !self.outranks(&self.heap[best], &self.heap[index]) {
+                return;
+            }
+            self.swap(index, best);
+            index = best;
+        }
+    }
+
+    /// Whether the waiter in `slot` ranks below the one in `other`.
+    fn ranks_below(&self, slot: usize, other: usize) -> bool {
+        let entry = &self.heap[self.slots[slot].heap_index];
+        let other = &self.heap[self.slots[other].heap_index];
+        self.outranks(other, entry)
+    }
+
+    fn remove_lowest(&mut self, slot: usize) {
+        let index = self.slots[slot].lowest_index;
+        self.lowest.swap_remove(index);
+        if index < self.lowest.len() {
+            self.slots[self.lowest[index]].lowest_index = index;
+            self.repair_lowest(index);
+        }
+    }
+
+    fn repair_lowest(&mut self, index: usize) {
+        if index > 0 && self.ranks_below(self.lowest[index], self.lowest[(index - 1) / 2]) {
+            self.sift_up_lowest(index);
+        } else {
+            self.sift_down_lowest(index);
+        }
+    }
+
+    fn rebuild_lowest(&mut self) {
+        for index in (0..self.lowest.len() / 2).rev() {
+            self.sift_down_lowest(index);
+        }
+    }
+
+    fn swap_lowest(&mut self, a: usize, b: usize) {
+        self.lowest.swap(a, b);
+        self.slots[self.lowest[a]].lowest_index = a;
+        self.slots[self.lowest[b]].lowest_index = b;
+    }
+
+    fn sift_up_lowest(&mut self, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            if !self.ranks_below(self.lowest[index], self.lowest[parent]) {
+                break;
+            }
+            self.swap_lowest(index, parent);
+            index = parent;
+        }
+    }
+
+    fn sift_down_lowest(&mut self, mut index: usize) {
+        loop {
+            let left = index * 2 + 1;
+            if left >= self.lowest.len() {
+                return;
+            }
+            let right = left + 1;
+            let worst = if right < self.lowest.len()
+                && self.ranks_below(self.lowest[right], self.lowest[left])
+            {
                 right
             } else {
                 left
             };
-            if !self.heap[best].outranks(&self.heap[index]) {
+            if !self.ranks_below(self.lowest[worst], self.lowest[index]) {
                 return;
             }
-            self.swap(index, best);
+            self.swap_lowest(index, worst);
+            index = worst;
+        }
+    }
+
+    fn fair_mut(&mut self) -> &mut FairQueue {
+        self.fair.as_mut().expect("fair sharing is active")
+    }
+
+    fn tenant_heap(&self, tenant: usize) -> &[usize] {
+        let fair = self.fair.as_ref().expect("fair sharing is active");
+        &fair.tenants[tenant].heap
+    }
+
+    fn join_tenant(&mut self, slot: usize, tenant: usize) {
+        let fair = self.fair_mut();
+        if fair.tenants[tenant].heap.is_empty() {
+            fair.activate(tenant);
+        }
+        let heap = &mut fair.tenants[tenant].heap;
+        let tenant_index = heap.len();
+        heap.push(slot);
+        self.slots[slot].tenant = tenant;
+        self.slots[slot].tenant_index = tenant_index;
+        self.sift_up_tenant(tenant, tenant_index);
+    }
+
+    fn leave_tenant(&mut self, slot: usize) {
+        let tenant = self.slots[slot].tenant;
+        let index = self.slots[slot].tenant_index;
+        let fair = self.fair_mut();
+        let heap = &mut fair.tenants[tenant].heap;
+        heap.swap_remove(index);
+        if heap.is_empty() {
+            fair.deactivate(tenant);
+        } else if index < heap.len() {
+            let moved = heap[index];
+            self.slots[moved].tenant_index = index;
+            self.repair_tenant(tenant, index);
+        }
+    }
+
+    fn repair_tenant(&mut self, tenant: usize, index: usize) {
+        let heap = self.tenant_heap(tenant);
+        if index > 0 && self.ranks_below(heap[(index - 1) / 2], heap[index]) {
+            self.sift_up_tenant(tenant, index);
+        } else {
+            self.sift_down_tenant(tenant, index);
+        }
+    }
+
+    fn rebuild_tenant(&mut self, tenant: usize) {
+        for index in (0..self.tenant_heap(tenant).len() / 2).rev() {
+            self.sift_down_tenant(tenant, index);
+        }
+    }
+
+    fn swap_tenant(&mut self, tenant: usize, a: usize, b: usize) {
+        let heap = &mut self.fair_mut().tenants[tenant].heap;
+        heap.swap(a, b);
+        let (slot_a, slot_b) = (heap[a], heap[b]);
+        self.slots[slot_a].tenant_index = a;
+        self.slots[slot_b].tenant_index = b;
+    }
+
+    fn sift_up_tenant(&mut self, tenant: usize, mut index: usize) {
+        while index > 0 {
+            let parent = (index - 1) / 2;
+            let heap = self.tenant_heap(tenant);
+            if !self.ranks_below(heap[parent], heap[index]) {
+                break;
+            }
+            self.swap_tenant(tenant, index, parent);
+            index = parent;
+        }
+    }
+
+    fn sift_down_tenant(&mut self, tenant: usize, mut index: usize) {
+        loop {
+            let heap = self.tenant_heap(tenant);
+            let left = index * 2 + 1;
+            if left >= heap.len() {
+                return;
+            }
+            let right = left + 1;
+            let best = if right < heap.len() && self.ranks_below(heap[left], heap[right]) {
+                right
+            } else {
+                left
+            };
+            if !self.ranks_below(heap[index], heap[best]) {
+                return;
+            }
+            self.swap_tenant(tenant, index, best);
             index = best;
         }
     }
 }
 
+impl WaitQueue<Priority> {
+    pub(crate) fn set_aging(&mut self, aging: Option<Aging>) {
+        self.aging = aging.map(|aging| {
+            let raise: Boost<Priority> = |priority, boost| priority.saturating_add(boost);
+            (aging, raise)
+        });
+        self.refresh_aging();
+        self.rebuild();
+    }
+
+    pub(crate) fn set_preemption_margin(&mut self, margin: Priority) {
+        let raise: Boost<Priority> = |priority, margin| priority.saturating_add(margin);
+        self.preemption_margin = (margin != 0).then_some((margin, raise));
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -222,10 +1146,10 @@ mod tests {
     fn priority_fifo_and_indexed_removal() {
         let mut queue = WaitQueue::new();
         let waker = noop_waker();
-        let low = queue.push(1, Arc::new(Waiter::new()), waker.clone());
-        let first_high = queue.push(9, Arc::new(Waiter::new()), waker.clone());
-        let cancelled = queue.push(100, Arc::new(Waiter::new()), waker.clone());
-        let second_high = queue.push(9, Arc::new(Waiter::new()), waker);
+        let low = queue.push(1, 1, Arc::new(Waiter::new()), waker.clone());
+        let first_high = queue.push(9, 1, Arc::new(Waiter::new()), waker.clone());
+        let cancelled = queue.push(100, 1, Arc::new(Waiter::new()), waker.clone());
+        let second_high = queue.push(9, 1, Arc::new(Waiter::new()), waker);
 
         assert!(queue.remove(cancelled).is_some());
         assert!(queue.remove(cancelled).is_none());
@@ -234,4 +1158,90 @@ mod tests {
         assert_eq!(queue.pop().unwrap().key.slot, low.slot);
         assert!(queue.is_empty());
     }
+
+    #[test]
+    fn skip_ahead_selects_the_best_request_that_fits() {
+        let mut queue = WaitQueue::new();
+        let waker = noop_waker();
+        queue.push(10, 4, Arc::new(Waiter::new()), waker.clone());
+        queue.push(1, 2, Arc::new(Waiter::new()), waker.clone());
+        let best_fit = queue.push(5, 2, Arc::new(Waiter::new()), waker.clone());
+        queue.push(7, 3, Arc::new(Waiter::new()), waker);
+
+        assert_eq!(queue.select(2), None);
+        assert_eq!(queue.select(4), Some((0, 4)));
+        queue.set_head_of_line(HeadOfLine::SkipAhead);
+        let (index, permits) = queue.select(2).unwrap();
+        assert_eq!(permits, 2);
+        assert_eq!(queue.take(index).key.slot, best_fit.slot);
+    }
+
+    #[test]
+    fn reprioritised_waiters_keep_their_arrival_order() {
+        let mut queue = WaitQueue::new();
+        let waker = noop_waker();
+        let early = queue.push(1, 1, Arc::new(Waiter::new()), waker.clone());
+        let high = queue.push(5, 1, Arc::new(Waiter::new()), waker.clone());
+        let late = queue.push(5, 1, Arc::new(Waiter::new()), waker);
+
+        assert!(queue.set_priority(early, 5));
+        assert!(queue.set_priority(high, 0));
+        assert_eq!(queue.pop().unwrap().key.slot, early.slot);
+        assert_eq!(queue.pop().unwrap().key.slot, late.slot);
+        let popped = queue.pop().unwrap().key;
+        assert_eq!(popped.slot, high.slot);
+        assert!(!queue.set_priority(popped, 9));
+    }
+
+    #[test]
+    fn bounded_queue_overflows_from_the_lowest_rank() {
+        let mut queue = WaitQueue::new();
+        let waker = noop_waker();
+        let low = queue.push(1, 1, Arc::new(Waiter::new()), waker.clone());
+        let middle = queue.push(5, 1, Arc::new(Waiter::new()), waker.clone());
+        queue.set_max_len(Some(2));
+        assert!(queue.pop_overflow().is_none());
+
+        let late_low = queue.push(1, 1, Arc::new(Waiter::new()), waker.clone());
+        assert_eq!(queue.pop_overflow().unwrap().key, late_low);
+        let high = queue.push(9, 1, Arc::new(Waiter::new()), waker);
+        assert_eq!(queue.pop_overflow().unwrap().key, low);
+
+        assert!(queue.set_priority(high, 0));
+        queue.set_max_len(Some(1));
+        assert_eq!(queue.pop_overflow().unwrap().key, high);
+        assert!(queue.pop_overflow().is_none());
+        assert_eq!(queue.pop().unwrap().key, middle);
+    }
+
+    #[test]
+    fn tenants_are_served_by_weight_and_then_by_rank() {
+        let mut queue = WaitQueue::new();
+        let waker = noop_waker();
+        queue.push(100, 1, Arc::new(Waiter::new()), waker.clone());
+        for priority in 0..6 {
+            let key = queue.push(priority, 1, Arc::new(Waiter::new()), waker.clone());
+            queue.set_tenant(key, 1, 2);
+        }
+        let mut cancelled = None;
+        for priority in 50..56 {
+            let key = queue.push(priority, 1, Arc::new(Waiter::new()), waker.clone());
+            queue.set_tenant(key, 2, 1);
+            cancelled = Some(key);
+        }
+        assert!(queue.remove(cancelled.unwrap()).is_some());
+
+        let mut order = Vec::new();
+        for _ in 0..8 {
+            let (index, _) = queue.select(1).unwrap();
+            order.push(queue.take(index).priority);
+        }
+        assert_eq!(order, [100, 5, 54, 4, 3, 53, 2, 1]);
+
+        // Fair sharing ends with the last waiter and starts afresh.
+        while let Some((index, _)) = queue.select(1) {
+            queue.take(index);
+        }
+        assert!(queue.fair.is_none());
+    }
 }
@@ -1,35 +1,68 @@
 //! Core implementation of [`PrioritySemaphore`].
 
+#[cfg(feature = "std")]
+use crate::timer::AcquireBy;
 use crate::{
-    error::{TryAcquireError, TryAcquireError::*},
+    error::{AcquireError, CloseReason, TryAcquireError, TryAcquireError::*},
     lock::Lock,
-    permit::Permit,
-    queue::{WaitKey, WaitQueue},
-    waiter::{AcquireFuture, Waiter},
+    permit::{Band, Permit, PermitRef, Revocation},
+    policy::{AdmissionRule, Aging, Busy, Cap, HeadOfLine, Reservation, SchedulingPolicy},
+    queue::{Labels, WaitKey, WaitQueue},
+    timer::{AcquireTimeout, Timer},
+    waiter::{
+        AcquireFuture, AcquirePreemptible, AcquireRefFuture, ClosedAndIdle, Waiter, WakeList,
+    },
 };
-use alloc::sync::Arc;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::{
-    sync::atomic::{AtomicUsize, Ordering},
-    task::Waker,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+    task::{Context, Poll, Waker},
+    time::Duration,
 };
+#[cfg(feature = "std")]
+use std::{cmp::Reverse, time::Instant};
 
-/// Priority value used by the semaphore.
+/// Default priority type used by the semaphore.
 ///
 /// Larger numbers represent higher priority. Waiters with an equal priority
 /// are served in first-in, first-out order.
 pub type Priority = i32;
 
+/// Earliest-deadline-first flavour of [`PrioritySemaphore`].
+///
+/// Waiters queued with [`DeadlineSemaphore::acquire_by`] are granted in
+/// deadline order, and fail with
+/// [`AcquireError::DeadlineExpired`](crate::AcquireError::DeadlineExpired)
+/// instead of being granted once their deadline has passed.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+pub type DeadlineSemaphore = PrioritySemaphore<Reverse<Instant>>;
+
 // Available permits and coordination flags share one atomic word. This closes
 // the check-then-enqueue race without putting the uncontended path behind a
 // mutex.
 const CLOSED: usize = 1 << (usize::BITS - 1);
 const HAS_WAITERS: usize = 1 << (usize::BITS - 2);
-const PERMIT_MASK: usize = HAS_WAITERS - 1;
+// Set while forgotten permits are still outstanding. Releases then take the
+// slow path so they can be absorbed instead of returned to the pool.
+const SHRINKING: usize = 1 << (usize::BITS - 3);
+// Set while the semaphore is closed and a `closed_and_idle` future waits.
+// Releases then take the slow path so the last one can wake it.
+const DRAINING: usize = 1 << (usize::BITS - 4);
+// Set between `pause` and `resume`. Permits stay in the pool.
+const PAUSED: usize = 1 << (usize::BITS - 5);
+const PERMIT_MASK: usize = PAUSED - 1;
+
+/// The parent of a child semaphore, with the clone that passes a priority up
+/// to it.
+pub(crate) type Parent<P> = (Arc<PrioritySemaphore<P>>, fn(&P) -> P);
 
 pub(crate) enum RegisterResult {
-    Acquired,
+    Acquired(Option<Arc<Band>>),
     Queued { key: WaitKey, waiter: Arc<Waiter> },
     Closed,
+    QueueFull,
+    Rejected(Busy),
 }
 
 /// A runtime-independent, priority-aware asynchronous semaphore.
@@ -37,64 +70,417 @@ pub(crate) enum RegisterResult {
 /// Acquiring an immediately available permit is lock-free. Under contention,
 /// returned permits are reserved directly for the highest-priority waiter, so
 /// a newly arriving task cannot steal a wake-up.
-pub struct PrioritySemaphore {
+///
+/// Waiters are ordered by a priority key of type `P`, where the greatest key
+/// is granted first. It defaults to [`Priority`], but any [`Ord`] type works,
+/// such as a `(tier, class)` tuple or `Reverse<Instant>` for the earliest
+/// deadline. Create such a semaphore with
+/// [`PrioritySemaphore::with_permits`].
+pub struct PrioritySemaphore<P: Ord = Priority> {
     state: AtomicUsize,
-    pub(crate) waiters: Lock<WaitQueue>,
-    max_permits: usize,
+    pub(crate) waiters: Lock<WaitQueue<P>>,
+    // Capacity and the number of outstanding permits still to be forgotten.
+    // Both are only modified with the queue lock held.
+    max_permits: AtomicUsize,
+    debt: AtomicUsize,
+    // Total of all reservations. Only modified with the queue lock held.
+    reserved: AtomicUsize,
+    // Whether any caps are installed. Only modified with the queue lock held.
+    capped: AtomicBool,
+    pub(crate) parent: Option<Parent<P>>,
+    // Outcomes of acquisitions made with a deadline.
+    #[cfg(feature = "std")]
+    deadlines_met: AtomicUsize,
+    #[cfg(feature = "std")]
+    deadlines_missed: AtomicUsize,
 }
 
-impl core::fmt::Debug for PrioritySemaphore {
+impl<P: Ord> core::fmt::Debug for PrioritySemaphore<P> {
     fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
         f.debug_struct("PrioritySemaphore")
             .field("available", &self.available_permits())
             .field("queued", &self.queued())
-            .field("max_permits", &self.max_permits)
+            .field("max_permits", &self.max_permits())
             .field("closed", &self.is_closed())
             .finish()
     }
 }
 
 impl PrioritySemaphore {
-    /// Creates a semaphore with `permits` concurrent permits.
+    /// Creates a semaphore with `permits` concurrent permits, ordered by
+    /// [`Priority`].
     ///
     /// # Panics
     ///
     /// Panics when `permits` is larger than [`PrioritySemaphore::MAX_PERMITS`].
     pub const fn new(permits: usize) -> Self {
-        assert!(permits <= Self::MAX_PERMITS, "too many semaphore permits");
+        Self::with_permits(permits)
+    }
+
+    /// Largest supported permit count.
+    pub const MAX_PERMITS: usize = PERMIT_MASK;
+
+    /// Enables or disables anti-starvation [`Aging`] for queued waiters.
+    ///
+    /// Aging is disabled by default. Changing it re-orders the waiters that
+    /// are already queued, and any that can now be granted are granted. It
+    /// raises priorities arithmetically, so it is only available with the
+    /// default [`Priority`] type.
+    pub fn set_aging(&self, aging: Option<Aging>) {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            queue.set_aging(aging);
+            if self.is_closed() {
+                return;
+            }
+            self.dispatch(&mut queue)
+        };
+        wake.wake_all();
+    }
+
+    /// Returns the current [`Aging`] configuration.
+    pub fn aging(&self) -> Option<Aging> {
+        self.waiters.lock().aging()
+    }
+
+    /// Sets how far above a [preemptible](PrioritySemaphore::acquire_preemptible)
+    /// holder a waiter must rank to revoke its permit.
+    ///
+    /// A waiter revokes holders whose priority plus `margin` is still below
+    /// its own, so the default of 0 lets any strictly greater priority ask
+    /// for permits back. A larger margin keeps work of similar priority from
+    /// interrupting each other. Holders are checked again the next time a
+    /// waiter queues or is re-prioritised. The margin is added to a
+    /// priority, so it is only available with the default [`Priority`] type.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `margin` is negative.
+    pub fn set_preemption_margin(&self, margin: Priority) {
+        assert!(margin >= 0, "preemption margin must not be negative");
+        self.waiters.lock().set_preemption_margin(margin);
+    }
+
+    /// Returns the current preemption margin.
+    pub fn preemption_margin(&self) -> Priority {
+        self.waiters.lock().preemption_margin()
+    }
+}
+
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+impl DeadlineSemaphore {
+    /// Acquires one permit, earliest `deadline` first.
+    ///
+    /// If `deadline` passes before a permit is granted, the future resolves
+    /// with [`AcquireError::DeadlineExpired`] instead. `timer` wakes a queued
+    /// waiter at the deadline so it fails promptly; the semaphore also checks
+    /// the deadline when it would otherwise grant the waiter, and when the
+    /// waiter heads the queue as another acquisition arrives. Re-prioritising
+    /// the future changes its place in the queue, but not the deadline it
+    /// fails at.
+    pub fn acquire_by<T: Timer>(
+        self: &Arc<Self>,
+        timer: &T,
+        deadline: Instant,
+    ) -> AcquireBy<T::Sleep> {
+        let sleep = timer.sleep(deadline.saturating_duration_since(Instant::now()));
+        AcquireBy::new(
+            self.acquire(Reverse(deadline)).with_deadline(deadline),
+            sleep,
+        )
+    }
+
+    /// Returns how many [`DeadlineSemaphore::acquire_by`] acquisitions were
+    /// granted before their deadline.
+    pub fn deadlines_met(&self) -> usize {
+        self.deadlines_met.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many [`DeadlineSemaphore::acquire_by`] acquisitions failed
+    /// because their deadline passed first.
+    pub fn deadlines_missed(&self) -> usize {
+        self.deadlines_missed.load(Ordering::Relaxed)
+    }
+}
+
+impl<P: Ord> PrioritySemaphore<P> {
+    /// Creates a semaphore with `permits` concurrent permits for any priority
+    /// type, for example `PrioritySemaphore::<(u8, u32)>::with_permits(4)`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `permits` is larger than [`PrioritySemaphore::MAX_PERMITS`].
+    pub const fn with_permits(permits: usize) -> Self {
+        assert!(permits <= PERMIT_MASK, "too many semaphore permits");
         Self {
             state: AtomicUsize::new(permits),
             waiters: Lock::new(WaitQueue::new()),
-            max_permits: permits,
+            max_permits: AtomicUsize::new(permits),
+            debt: AtomicUsize::new(0),
+            reserved: AtomicUsize::new(0),
+            capped: AtomicBool::new(false),
+            parent: None,
+            #[cfg(feature = "std")]
+            deadlines_met: AtomicUsize::new(0),
+            #[cfg(feature = "std")]
+            deadlines_missed: AtomicUsize::new(0),
         }
     }
 
-    /// Largest supported initial permit count.
-    pub const MAX_PERMITS: usize = PERMIT_MASK;
+    /// Creates a child semaphore with `limit` permits of its own.
+    ///
+    /// Every permit of the child also holds a permit of this semaphore. An
+    /// acquisition on the child first takes the child's permits and then
+    /// waits for the parent's at the same priority, so acquisitions always
+    /// climb the tree in one direction and cannot deadlock against each
+    /// other. A permit returns the child's permits before the parent's, so
+    /// holding a child permit always implies holding a parent permit.
+    /// Children can have children of their own, and closing a parent fails
+    /// the acquisitions still waiting for it.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `limit` is larger than [`PrioritySemaphore::MAX_PERMITS`].
+    pub fn child(self: &Arc<Self>, limit: usize) -> Arc<Self>
+    where
+        P: Clone,
+    {
+        let mut child = Self::with_permits(limit);
+        child.parent = Some((self.clone(), P::clone));
+        Arc::new(child)
+    }
+
+    /// Returns the parent of a semaphore created with
+    /// [`PrioritySemaphore::child`].
+    pub fn parent(&self) -> Option<&Arc<Self>> {
+        self.parent.as_ref().map(|(parent, _)| parent)
+    }
 
     /// Acquires one permit at `priority`.
     ///
     /// The returned future is cancellation-safe. If it is cancelled after a
     /// permit has already been assigned, that permit is immediately passed to
     /// the next waiter or returned to the semaphore.
-    pub fn acquire(self: &Arc<Self>, priority: Priority) -> AcquireFuture {
-        AcquireFuture::new(self.clone(), priority)
+    pub fn acquire(self: &Arc<Self>, priority: P) -> AcquireFuture<P> {
+        AcquireFuture::new(self.clone(), priority, 1)
+    }
+
+    /// Acquires `permits` permits at `priority` as a single request.
+    ///
+    /// The request is queued as one waiter. Returned permits accumulate for
+    /// it until the whole request can be handed off, and they cannot be
+    /// stolen by newly arriving acquisitions in the meantime, whatever their
+    /// priority. A request larger than the semaphore's capacity waits until
+    /// the capacity grows or the semaphore is closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `permits` is larger than [`PrioritySemaphore::MAX_PERMITS`].
+    pub fn acquire_many(self: &Arc<Self>, permits: usize, priority: P) -> AcquireFuture<P> {
+        assert!(permits <= PERMIT_MASK, "too many semaphore permits");
+        AcquireFuture::new(self.clone(), priority, permits)
+    }
+
+    /// Acquires one permit at `priority` as part of `tenant`'s fair share.
+    ///
+    /// While acquisitions from several tenants are queued, permits are
+    /// handed to tenants in proportion to their `weight` instead of by
+    /// priority, so one busy tenant cannot take all of the capacity.
+    /// `priority` still orders the requests within a tenant. A tenant's
+    /// weight is the one given by its latest queued acquisition. Plain
+    /// acquisitions share the queue as one more tenant of weight 1. Tenants
+    /// only compete while they wait at the same time; an idle tenant does
+    /// not build up credit.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `weight` is zero.
+    pub fn acquire_for(
+        self: &Arc<Self>,
+        tenant: u64,
+        weight: u32,
+        priority: P,
+    ) -> AcquireFuture<P> {
+        assert!(weight != 0, "tenant weight must be positive");
+        AcquireFuture::new(self.clone(), priority, 1).for_tenant(tenant, weight)
+    }
+
+    /// Acquires one permit at `priority` that higher-priority work can ask
+    /// back.
+    ///
+    /// While the permit is held, a waiter that queues or is raised to a
+    /// strictly greater priority, by more than the
+    /// [preemption margin](PrioritySemaphore::set_preemption_margin), revokes
+    /// the lowest-priority preemptible holders, newest first among equals,
+    /// until they cover its request. Holders already revoked for the waiter
+    /// count toward it, so raising it again only revokes what is missing.
+    /// The holder learns of it through [`Permit::revoked`] or
+    /// [`Permit::is_revoked`] and is expected to check-point and drop the
+    /// permit; revocation does not take the permit away by itself.
+    pub fn acquire_preemptible(self: &Arc<Self>, priority: P) -> AcquirePreemptible<P>
+    where
+        P: Clone,
+    {
+        AcquirePreemptible::new(self.acquire(priority.clone()), priority)
     }
 
     /// Attempts to acquire one immediately available permit.
     ///
-    /// This method never bypasses already queued waiters. `priority` is
-    /// accepted for API symmetry, but only affects queued acquisitions.
-    pub fn try_acquire(self: &Arc<Self>, _priority: Priority) -> Result<Permit, TryAcquireError> {
-        self.try_take()?;
-        Ok(Permit::new(self.clone()))
+    /// This method never bypasses queued waiters that are waiting for permits
+    /// to be returned, whatever their priority. It passes over the queue only
+    /// when every queued waiter is held back by a [`Reservation`] or a full
+    /// [`Cap`] instead. Otherwise `priority` does not affect the outcome
+    /// beyond the reservations it may use, unless an [`AdmissionRule`]
+    /// rejects it, in which case the error is [`TryAcquireError::Rejected`]
+    /// rather than [`TryAcquireError::NoPermits`].
+    pub fn try_acquire(self: &Arc<Self>, priority: P) -> Result<Permit<P>, TryAcquireError> {
+        self.try_acquire_many(1, priority)
+    }
+
+    /// Attempts to acquire `permits` immediately available permits at once.
+    ///
+    /// Like [`PrioritySemaphore::try_acquire`], this never bypasses queued
+    /// waiters that are waiting for permits to be returned.
+    pub fn try_acquire_many(
+        self: &Arc<Self>,
+        permits: usize,
+        priority: P,
+    ) -> Result<Permit<P>, TryAcquireError> {
+        let band = self.try_admit(permits, &priority)?;
+        let permit = Permit::new(self.clone(), permits, band);
+        match &self.parent {
+            // A failure here drops the child's permits again.
+            Some((parent, _)) => {
+                Ok(permit.with_parent(parent.try_acquire_many(permits, priority)?))
+            }
+            None => Ok(permit),
+        }
+    }
+
+    /// Acquires one permit at `priority` without requiring an [`Arc`].
+    ///
+    /// The returned [`PermitRef`] borrows the semaphore, which makes this
+    /// usable with `static` semaphores and avoids reference-count updates.
+    /// Ordering and cancellation behave exactly like
+    /// [`PrioritySemaphore::acquire`].
+    pub fn acquire_ref(&self, priority: P) -> AcquireRefFuture<'_, P> {
+        AcquireRefFuture::new(self, priority, 1)
+    }
+
+    /// Borrowing counterpart of [`PrioritySemaphore::acquire_many`].
+    ///
+    /// # Panics
+    ///
+    /// Panics when `permits` is larger than [`PrioritySemaphore::MAX_PERMITS`].
+    pub fn acquire_many_ref(&self, permits: usize, priority: P) -> AcquireRefFuture<'_, P> {
+        assert!(permits <= PERMIT_MASK, "too many semaphore permits");
+        AcquireRefFuture::new(self, priority, permits)
+    }
+
+    /// Borrowing counterpart of [`PrioritySemaphore::try_acquire`].
+    pub fn try_acquire_ref(&self, priority: P) -> Result<PermitRef<'_, P>, TryAcquireError> {
+        self.try_acquire_many_ref(1, priority)
+    }
+
+    /// Borrowing counterpart of [`PrioritySemaphore::try_acquire_many`].
+    pub fn try_acquire_many_ref(
+        &self,
+        permits: usize,
+        priority: P,
+    ) -> Result<PermitRef<'_, P>, TryAcquireError> {
+        let band = self.try_admit(permits, &priority)?;
+        let permit = PermitRef::new(self, permits, band);
+        match &self.parent {
+            Some((parent, _)) => {
+                Ok(permit.with_parent(parent.try_acquire_many(permits, priority)?))
+            }
+            None => Ok(permit),
+        }
+    }
+
+    /// Acquires one permit at `priority`, giving up after `timeout`.
+    ///
+    /// On expiry the future resolves with
+    /// [`AcquireError::Timeout`](crate::AcquireError::Timeout) and
+    /// withdraws from the queue. Cancellation safety is the same as for
+    /// [`PrioritySemaphore::acquire`], including after a direct handoff.
+    pub fn acquire_timeout<T: Timer>(
+        self: &Arc<Self>,
+        timer: &T,
+        priority: P,
+        timeout: Duration,
+    ) -> AcquireTimeout<T::Sleep, P> {
+        AcquireTimeout::new(self.acquire(priority), timer.sleep(timeout))
+    }
+
+    /// Acquires one permit at `priority`, giving up at `deadline`.
+    ///
+    /// Behaves like [`PrioritySemaphore::acquire_timeout`] with an absolute
+    /// deadline in the timer's own clock.
+    pub fn acquire_until<T: Timer>(
+        self: &Arc<Self>,
+        timer: &T,
+        priority: P,
+        deadline: T::Instant,
+    ) -> AcquireTimeout<T::Sleep, P> {
+        AcquireTimeout::new(self.acquire(priority), timer.sleep_until(deadline))
+    }
+
+    /// Blocks the current thread until one permit is acquired at `priority`.
+    ///
+    /// The thread is queued exactly like an async waiter and takes part in
+    /// the same priority order and direct handoff. It is parked while it
+    /// waits, so this must not be called from inside an async task.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn acquire_blocking(self: &Arc<Self>, priority: P) -> Result<Permit<P>, AcquireError> {
+        crate::blocking::block_on(self.acquire(priority), None)
+            .expect("blocking acquire without a deadline cannot time out")
+    }
+
+    /// Like [`PrioritySemaphore::acquire_blocking`], but gives up after
+    /// `timeout` with [`AcquireError::Timeout`].
+    ///
+    /// A permit handed to this thread just as the timeout expires is passed
+    /// on to the next waiter, as if the acquire future had been dropped.
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn acquire_blocking_timeout(
+        self: &Arc<Self>,
+        priority: P,
+        timeout: Duration,
+    ) -> Result<Permit<P>, AcquireError> {
+        // An overflowing deadline is treated as no deadline at all.
+        let deadline = std::time::Instant::now().checked_add(timeout);
+        crate::blocking::block_on(self.acquire(priority), deadline)
+            .unwrap_or(Err(AcquireError::Timeout))
     }
 
     /// Closes the semaphore and wakes every queued waiter.
     ///
     /// Closing is idempotent. Permits acquired before the close remain valid,
-    /// while all subsequent acquisition attempts fail.
+    /// while all subsequent acquisition attempts fail until
+    /// [`PrioritySemaphore::reopen`].
     pub fn close(&self) {
+        self.shut(None);
+    }
+
+    /// Closes the semaphore like [`PrioritySemaphore::close`], recording why.
+    ///
+    /// Every queued waiter and every later acquisition attempt fails with
+    /// [`AcquireError::ClosedWith`] or [`TryAcquireError::ClosedWith`]
+    /// carrying `reason`, so callers can tell a shutdown from a reload or a
+    /// tripped circuit breaker. Only the first close records a reason; it is
+    /// cleared by [`PrioritySemaphore::reopen`].
+    pub fn close_with<E>(&self, reason: E)
+    where
+        E: core::error::Error + Send + Sync + 'static,
+    {
+        self.shut(Some(CloseReason::new(reason)));
+    }
+
+    fn shut(&self, reason: Option<CloseReason>) {
         let entries = {
             // The lock makes close and direct handoff linearisable with each
             // other. Wakers are deliberately invoked after it is released.
@@ -105,15 +491,340 @@ impl PrioritySemaphore {
             }
             let entries = queue.drain();
             for entry in &entries {
-                entry.waiter.close();
+                entry.waiter.close(reason.clone());
             }
+            queue.set_close_reason(reason);
             self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
-            entries
+            let mut idle = WakeList::new();
+            if queue.has_idle_wakers() {
+                self.state.fetch_or(DRAINING, Ordering::AcqRel);
+                self.wake_idle(&mut queue, &mut idle);
+            }
+            (entries, idle)
         };
 
-        for entry in entries {
+        for entry in entries.0 {
             entry.waker.wake();
         }
+        entries.1.wake_all();
+    }
+
+    /// Fails the queued waiters for which `matches(priority, tag)` returns
+    /// `true`, returning how many were cancelled.
+    ///
+    /// `priority` is the priority the waiter asked for, before any
+    /// [`Aging`], and `tag` is the tag given to [`AcquireFuture::with_tag`],
+    /// if any. `matches` runs with the queue lock held, so it must not call
+    /// back into this semaphore, which would deadlock. Cancelled waiters fail
+    /// with [`AcquireError::Cancelled`]; the others keep their place, and may
+    /// be granted the permits that were accumulating for a cancelled head.
+    /// Acquisitions that have not queued yet, and permits already granted,
+    /// are not affected.
+    pub fn cancel_waiters_where<F>(&self, matches: F) -> usize
+    where
+        F: FnMut(&P, Option<u64>) -> bool,
+    {
+        let (cancelled, wake) = {
+            let mut queue = self.waiters.lock();
+            let entries = queue.remove_where(matches);
+            let cancelled = entries.len();
+            let mut wake = self.dispatch(&mut queue);
+            for entry in entries {
+                entry.waiter.cancel();
+                wake.push(entry.waker);
+            }
+            (cancelled, wake)
+        };
+        wake.wake_all();
+        cancelled
+    }
+
+    /// Fails every queued waiter with a priority below `priority`, like
+    /// [`PrioritySemaphore::cancel_waiters_where`].
+    pub fn cancel_below(&self, priority: P) -> usize {
+        self.cancel_waiters_where(|queued, _| *queued < priority)
+    }
+
+    /// Stops granting permits until [`PrioritySemaphore::resume`], without
+    /// failing anyone.
+    ///
+    /// While paused, acquisitions queue instead of taking available permits,
+    /// queued waiters keep their place, and returned permits stay in the pool
+    /// instead of being handed off. Immediate acquisitions fail with
+    /// [`TryAcquireError::Paused`]. Pausing is idempotent and independent of
+    /// [`PrioritySemaphore::close`].
+    pub fn pause(&self) {
+        let _queue = self.waiters.lock();
+        self.state.fetch_or(PAUSED, Ordering::AcqRel);
+    }
+
+    /// Resumes granting after [`PrioritySemaphore::pause`], serving the
+    /// queued waiters from the pool in priority order.
+    pub fn resume(&self) {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            let previous = self.state.fetch_and(!PAUSED, Ordering::AcqRel);
+            if previous & PAUSED == 0 || previous & CLOSED != 0 {
+                return;
+            }
+            self.dispatch(&mut queue)
+        };
+        wake.wake_all();
+    }
+
+    /// Returns `true` between [`PrioritySemaphore::pause`] and
+    /// [`PrioritySemaphore::resume`].
+    pub fn is_paused(&self) -> bool {
+        self.state.load(Ordering::Acquire) & PAUSED != 0
+    }
+
+    /// Waits until the semaphore is closed and every permit has been
+    /// returned.
+    ///
+    /// Permits assigned by direct handoff to a future that has not been
+    /// polled since count as outstanding until that future completes and its
+    /// permit is dropped, or until the future itself is dropped. This does
+    /// not close the semaphore; call [`PrioritySemaphore::close`] first. If
+    /// the semaphore is reopened meanwhile, the future keeps waiting for the
+    /// next close.
+    pub fn closed_and_idle(&self) -> ClosedAndIdle<'_, P> {
+        ClosedAndIdle::new(self)
+    }
+
+    /// Blocks the current thread until the semaphore is closed and every
+    /// permit has been returned, like
+    /// [`PrioritySemaphore::closed_and_idle`].
+    #[cfg(feature = "std")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+    pub fn closed_and_idle_blocking(&self) {
+        crate::blocking::block_on(self.closed_and_idle(), None);
+    }
+
+    /// Reopens a closed semaphore, so it accepts new acquisitions again.
+    ///
+    /// Permits returned while the semaphore was closed are still counted, and
+    /// permits held across the close and reopen stay valid and are returned
+    /// as usual, so the capacity is unchanged. Waiters already failed by the
+    /// close are not revived. Reopening an open semaphore does nothing.
+    pub fn reopen(&self) {
+        // The lock orders the reopen after a close's drain and before any
+        // waiter registers again.
+        let mut queue = self.waiters.lock();
+        // Futures still waiting for idleness wait for the next close.
+        self.state.fetch_and(!(CLOSED | DRAINING), Ordering::AcqRel);
+        queue.set_close_reason(None);
+    }
+
+    /// Sets how a multi-permit request at the head of the queue affects the
+    /// waiters behind it.
+    ///
+    /// The new policy applies immediately: switching to a skip-ahead policy
+    /// grants any queued requests that fit into the currently pooled permits.
+    pub fn set_head_of_line(&self, policy: HeadOfLine) {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            queue.set_head_of_line(policy);
+            if self.is_closed() {
+                return;
+            }
+            self.dispatch(&mut queue)
+        };
+        wake.wake_all();
+    }
+
+    /// Returns the current [`HeadOfLine`] policy.
+    pub fn head_of_line(&self) -> HeadOfLine {
+        self.waiters.lock().head_of_line()
+    }
+
+    /// Replaces the policy that orders queued waiters.
+    ///
+    /// The default is [`PriorityFifo`](crate::PriorityFifo). Waiters that are
+    /// already queued are re-ordered under the new policy, and any that can
+    /// now be granted are granted.
+    pub fn set_scheduling_policy<S: SchedulingPolicy<P> + 'static>(&self, policy: S) {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            queue.set_policy(Box::new(policy));
+            if self.is_closed() {
+                return;
+            }
+            self.dispatch(&mut queue)
+        };
+        wake.wake_all();
+    }
+
+    /// Limits how many acquisitions may wait in the queue at once.
+    ///
+    /// When the queue is full, a new acquisition that outranks the
+    /// lowest-ranked waiter takes its place, and the evicted waiter fails with
+    /// [`AcquireError::Evicted`](crate::AcquireError::Evicted). Otherwise the
+    /// new acquisition fails with
+    /// [`AcquireError::QueueFull`](crate::AcquireError::QueueFull). Lowering
+    /// the limit evicts the lowest-ranked waiters immediately. `None`, the
+    /// default, leaves the queue unbounded.
+    pub fn set_max_queued(&self, max_queued: Option<usize>) {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            queue.set_max_len(max_queued);
+            let mut wake = WakeList::new();
+            self.shed(&mut queue, None, &mut wake);
+            wake
+        };
+        wake.wake_all();
+    }
+
+    /// Returns the current queue limit.
+    pub fn max_queued(&self) -> Option<usize> {
+        self.waiters.lock().max_len()
+    }
+
+    /// Replaces the [`AdmissionRule`]s that turn away low-priority
+    /// acquisitions while the semaphore is busy.
+    ///
+    /// A rejected `acquire` fails with
+    /// [`AcquireError::Rejected`](crate::AcquireError::Rejected) instead of
+    /// queueing, and a rejected `try_acquire` with
+    /// [`TryAcquireError::Rejected`]. Both carry the [`Busy`] condition of the
+    /// first rule that applied. Acquisitions that can be granted at once and
+    /// waiters that are already queued are never rejected. Pass an empty list
+    /// to admit everything again, which is the default.
+    pub fn set_admission_rules<I>(&self, rules: I)
+    where
+        I: IntoIterator<Item = AdmissionRule<P>>,
+    {
+        self.waiters
+            .lock()
+            .set_admission(rules.into_iter().collect());
+    }
+
+    /// Returns the current [`AdmissionRule`]s.
+    pub fn admission_rules(&self) -> Vec<AdmissionRule<P>>
+    where
+        P: Clone,
+    {
+        self.waiters.lock().admission().to_vec()
+    }
+
+    /// Replaces the permits reserved for priority bands.
+    ///
+    /// See [`Reservation`]. Reservations apply to immediate acquisitions and
+    /// to handoff alike. Lowering them hands any newly usable permits to
+    /// queued waiters. Pass an empty list to remove them, which is the
+    /// default.
+    pub fn set_reservations<I>(&self, reservations: I)
+    where
+        I: IntoIterator<Item = Reservation<P>>,
+    {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            let reserved = queue.set_reservations(reservations.into_iter().collect());
+            self.reserved.store(reserved, Ordering::Release);
+            if self.is_closed() {
+                return;
+            }
+            self.dispatch(&mut queue)
+        };
+        wake.wake_all();
+    }
+
+    /// Returns the current [`Reservation`]s.
+    pub fn reservations(&self) -> Vec<Reservation<P>>
+    where
+        P: Clone,
+    {
+        self.waiters.lock().reservations().to_vec()
+    }
+
+    /// Replaces the [`Cap`]s that limit how many permits low-priority bands
+    /// may hold at once.
+    ///
+    /// Holdings are tracked through the permits granted in each band, so a
+    /// band counts its permits until they are returned or forgotten. Permits
+    /// granted before a band's cap was installed do not count toward it;
+    /// replacing a cap with the same bound keeps its count. Raising a cap
+    /// hands newly usable permits to queued waiters. Pass an empty list to
+    /// remove them, which is the default.
+    pub fn set_caps<I>(&self, caps: I)
+    where
+        I: IntoIterator<Item = Cap<P>>,
+    {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            let capped = queue.set_caps(caps.into_iter().collect());
+            self.capped.store(capped, Ordering::Release);
+            if self.is_closed() {
+                return;
+            }
+            self.dispatch(&mut queue)
+        };
+        wake.wake_all();
+    }
+
+    /// Returns the current [`Cap`]s, in ascending order of their bound.
+    pub fn caps(&self) -> Vec<Cap<P>>
+    where
+        P: Clone,
+    {
+        self.waiters.lock().caps()
+    }
+
+    /// Adds `permits` to the semaphore's capacity.
+    ///
+    /// The new permits first settle any reduction from
+    /// [`PrioritySemaphore::forget_permits`] that is still pending, and are
+    /// then handed to queued waiters in priority order.
+    ///
+    /// # Panics
+    ///
+    /// Panics when the capacity would exceed [`PrioritySemaphore::MAX_PERMITS`].
+    pub fn add_permits(&self, permits: usize) {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            self.grow(permits, &mut queue)
+        };
+        wake.wake_all();
+    }
+
+    /// Removes `permits` from the semaphore's capacity.
+    ///
+    /// Available permits are removed immediately. The rest are taken back as
+    /// held permits are returned, before any of them reach a waiter, so this
+    /// never fails. A count larger than the capacity shrinks it to zero.
+    pub fn forget_permits(&self, permits: usize) {
+        let _queue = self.waiters.lock();
+        self.shrink(permits);
+    }
+
+    /// Grows or shrinks the capacity to `permits`.
+    ///
+    /// Growing behaves like [`PrioritySemaphore::add_permits`] and shrinking
+    /// like [`PrioritySemaphore::forget_permits`].
+    ///
+    /// # Panics
+    ///
+    /// Panics when `permits` is larger than [`PrioritySemaphore::MAX_PERMITS`].
+    pub fn resize(&self, permits: usize) {
+        assert!(permits <= PERMIT_MASK, "too many semaphore permits");
+        let wake = {
+            let mut queue = self.waiters.lock();
+            let capacity = self.max_permits.load(Ordering::Relaxed);
+            if permits >= capacity {
+                self.grow(permits - capacity, &mut queue)
+            } else {
+                self.shrink(capacity - permits);
+                return;
+            }
+        };
+        wake.wake_all();
+    }
+
+    /// Returns the current capacity.
+    ///
+    /// After a shrink, this is the new capacity even while some of the
+    /// removed permits are still held.
+    pub fn max_permits(&self) -> usize {
+        self.max_permits.load(Ordering::Acquire)
     }
 
     /// Returns the number of permits that can be acquired immediately.
@@ -131,56 +842,196 @@ impl PrioritySemaphore {
         self.state.load(Ordering::Acquire) & CLOSED != 0
     }
 
-    pub(crate) fn register(&self, priority: Priority, waker: &Waker) -> RegisterResult {
-        let mut queue = self.waiters.lock();
+    pub(crate) fn register(
+        &self,
+        priority: P,
+        permits: usize,
+        labels: Labels,
+        #[cfg(feature = "std")] deadline: Option<Instant>,
+        waker: &Waker,
+    ) -> RegisterResult {
+        let (result, wake, expired) = {
+            let mut queue = self.waiters.lock();
+            // Expired waiters at the head would otherwise hold up the arrival.
+            #[cfg(feature = "std")]
+            let expired = self.expire_heads(&mut queue);
+            #[cfg(not(feature = "std"))]
+            let expired = WakeList::new();
+            let (result, wake) = self.enqueue(
+                &mut queue,
+                priority,
+                permits,
+                labels,
+                #[cfg(feature = "std")]
+                deadline,
+                waker,
+            );
+            (result, wake, expired)
+        };
+        expired.wake_all();
+        wake.wake_all();
+        result
+    }
+
+    /// Queues an acquisition unless it can be granted or turned away at once.
+    /// Must be called with the queue lock held.
+    fn enqueue(
+        &self,
+        queue: &mut WaitQueue<P>,
+        priority: P,
+        permits: usize,
+        labels: Labels,
+        #[cfg(feature = "std")] deadline: Option<Instant>,
+        waker: &Waker,
+    ) -> (RegisterResult, WakeList) {
         let previous = self.state.fetch_or(HAS_WAITERS, Ordering::AcqRel);
         if previous & CLOSED != 0 {
             if queue.is_empty() {
                 self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
             }
-            return RegisterResult::Closed;
+            return (RegisterResult::Closed, WakeList::new());
         }
 
-        // Only the first waiter can consume a permit that raced with queue
-        // registration. Existing queued waiters must retain strict priority.
-        if queue.is_empty() {
-            let state = self.state.load(Ordering::Acquire);
-            debug_assert_eq!(state & CLOSED, 0);
-            if state & PERMIT_MASK != 0 {
-                match self.state.compare_exchange(
-                    state,
-                    state - 1,
-                    Ordering::AcqRel,
-                    Ordering::Acquire,
-                ) {
-                    Ok(_) => {
-                        self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
-                        return RegisterResult::Acquired;
-                    }
-                    Err(actual) => {
-                        // A release that began before HAS_WAITERS was set may
-                        // have changed the count once. It cannot keep changing
-                        // while this queue lock is held.
-                        if actual & PERMIT_MASK != 0 {
-                            let taken = self.state.compare_exchange(
-                                actual,
-                                actual - 1,
-                                Ordering::AcqRel,
-                                Ordering::Acquire,
-                            );
-                            if taken.is_ok() {
-                                self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
-                                return RegisterResult::Acquired;
-                            }
-                        }
-                    }
-                }
+        // Only the first waiter can consume permits that raced with queue
+        // registration. Existing queued waiters must retain strict
+        // priority. A release that began before HAS_WAITERS was set may
+        // still change the count once; take_pooled retries in that case.
+        let band = queue.band(&priority);
+        if queue.is_empty()
+            && previous & PAUSED == 0
+            && band.is_none_or(|band| band.has_room(permits))
+            && self.take_pooled(permits, queue.headroom(&priority))
+        {
+            self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
+            let band = band.cloned();
+            if let Some(band) = &band {
+                band.charge(permits);
             }
+            return (RegisterResult::Acquired(band), WakeList::new());
         }
 
+        if let Some(reason) = queue.reject(&priority, self.available_permits()) {
+            if queue.is_empty() {
+                self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
+            }
+            return (RegisterResult::Rejected(reason), WakeList::new());
+        }
+
+        // Permits pooled for a waiter that is only short of returned permits
+        // stay with the queue, as they do for try_acquire, whatever the rank
+        // of the new waiter. Otherwise the new waiter may use them at once.
+        let admitted = queue.admits_arrival(self.available_permits());
         let waiter = Arc::new(Waiter::new());
-        let key = queue.push(priority, waiter.clone(), waker.clone());
-        RegisterResult::Queued { key, waiter }
+        let key = queue.push(priority, permits, waiter.clone(), waker.clone());
+        if let Some((tenant, weight)) = labels.tenant {
+            queue.set_tenant(key, tenant, weight);
+        }
+        if let Some(tag) = labels.tag {
+            queue.set_tag(key, tag);
+        }
+        #[cfg(feature = "std")]
+        if let Some(deadline) = deadline {
+            queue.set_deadline(key, deadline);
+        }
+        let mut wake = if admitted {
+            self.dispatch(queue)
+        } else {
+            WakeList::new()
+        };
+        if self.shed(queue, Some(key), &mut wake) {
+            (RegisterResult::QueueFull, wake)
+        } else {
+            queue.preempt(key, &mut wake);
+            (RegisterResult::Queued { key, waiter }, wake)
+        }
+    }
+
+    pub(crate) fn add_holder(&self, priority: P, permits: usize) -> Arc<Revocation> {
+        let revocation = Arc::new(Revocation::new());
+        self.waiters
+            .lock()
+            .add_holder(priority, permits, revocation.clone());
+        revocation
+    }
+
+    pub(crate) fn remove_holder(&self, revocation: &Arc<Revocation>) {
+        self.waiters.lock().remove_holder(revocation);
+    }
+
+    pub(crate) fn poll_idle(&self, id: &mut Option<u64>, cx: &mut Context<'_>) -> Poll<()> {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            if !self.is_closed() {
+                // Closing starts the drain once it finds the future listed.
+                queue.add_idle_waker(id, cx.waker());
+                return Poll::Pending;
+            }
+            // Divert releases to the slow path before checking, so the last
+            // one cannot return its permits unnoticed.
+            self.state.fetch_or(DRAINING, Ordering::AcqRel);
+            if !self.is_idle() {
+                queue.add_idle_waker(id, cx.waker());
+                return Poll::Pending;
+            }
+            if let Some(id) = id.take() {
+                queue.remove_idle_waker(id);
+            }
+            let mut wake = WakeList::new();
+            self.wake_idle(&mut queue, &mut wake);
+            wake
+        };
+        wake.wake_all();
+        Poll::Ready(())
+    }
+
+    /// Withdraws a dropped `closed_and_idle` future, ending the drain if it
+    /// was the last one waiting.
+    pub(crate) fn forget_idle(&self, id: u64) {
+        let mut queue = self.waiters.lock();
+        queue.remove_idle_waker(id);
+        if !queue.has_idle_wakers() {
+            self.state.fetch_and(!DRAINING, Ordering::Release);
+        }
+    }
+
+    /// Whether the semaphore is closed with every permit back in the pool.
+    /// Must be called with the queue lock held.
+    fn is_idle(&self) -> bool {
+        let state = self.state.load(Ordering::Acquire);
+        state & CLOSED != 0
+            && self.debt.load(Ordering::Relaxed) == 0
+            && state & PERMIT_MASK == self.max_permits.load(Ordering::Relaxed)
+    }
+
+    /// Collects the wakers of `closed_and_idle` futures once the semaphore is
+    /// idle. Must be called with the queue lock held.
+    fn wake_idle(&self, queue: &mut WaitQueue<P>, wake: &mut WakeList) {
+        if self.state.load(Ordering::Acquire) & DRAINING == 0 || !self.is_idle() {
+            return;
+        }
+        self.state.fetch_and(!DRAINING, Ordering::Release);
+        for waker in queue.take_idle_wakers() {
+            wake.push(waker);
+        }
+    }
+
+    /// The error for an acquisition that found the semaphore closed.
+    pub(crate) fn closed_error(&self) -> AcquireError {
+        let queue = self.waiters.lock();
+        queue
+            .close_reason()
+            .cloned()
+            .map_or(AcquireError::Closed, AcquireError::ClosedWith)
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn deadline_met(&self) {
+        self.deadlines_met.fetch_add(1, Ordering::Relaxed);
+    }
+
+    #[cfg(feature = "std")]
+    pub(crate) fn deadline_missed(&self) {
+        self.deadlines_missed.fetch_add(1, Ordering::Relaxed);
     }
 
     pub(crate) fn refresh_waker(&self, key: WaitKey, waiter: &Waiter, waker: &Waker) {
@@ -190,36 +1041,62 @@ impl PrioritySemaphore {
         }
     }
 
-    pub(crate) fn cancel_waiter(&self, key: WaitKey, waiter: &Waiter) {
-        let assigned = {
+    pub(crate) fn reprioritize(&self, key: WaitKey, waiter: &Waiter, priority: P) -> bool {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            let raised = queue
+                .priority_of(key)
+                .is_some_and(|current| *current < priority);
+            if !waiter.is_waiting() || !queue.set_priority(key, priority) {
+                return false;
+            }
+            // A raised request may now head the queue and fit into permits
+            // pooled for the previous head, or outrank preemptible holders.
+            let mut wake = self.dispatch(&mut queue);
+            if raised {
+                queue.preempt(key, &mut wake);
+            }
+            wake
+        };
+        wake.wake_all();
+        true
+    }
+
+    pub(crate) fn cancel_waiter(&self, key: WaitKey, waiter: &Waiter, permits: usize) {
+        let wake = {
             let mut queue = self.waiters.lock();
             if waiter.is_waiting() {
-                let removed = queue.remove(key);
-                if removed.is_some() && queue.is_empty() {
-                    self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
-                }
-                false
+                // Removing a blocked head can unblock smaller requests behind
+                // it with the permits that were accumulating for it.
+                queue.remove(key);
+                Some(self.dispatch(&mut queue))
             } else {
-                waiter.is_assigned()
+                None
             }
         };
 
-        if assigned {
-            self.release_one();
+        match wake {
+            Some(wake) => wake.wake_all(),
+            None if waiter.is_assigned() => {
+                self.release_from(permits, waiter.take_band().as_deref())
+            }
+            None => {}
         }
     }
 
-    pub(crate) fn release_one(&self) {
+    pub(crate) fn release(&self, permits: usize) {
         let mut state = self.state.load(Ordering::Acquire);
         loop {
-            if state & HAS_WAITERS != 0 {
-                self.release_slow();
+            if state & (HAS_WAITERS | SHRINKING | DRAINING) != 0 {
+                self.release_slow(permits);
                 return;
             }
-            debug_assert!((state & PERMIT_MASK) < self.max_permits);
+            // Capacity may change concurrently, so only overflow is checked
+            // here. The slow path checks against the live capacity.
+            debug_assert!(permits <= PERMIT_MASK - (state & PERMIT_MASK));
             match self.state.compare_exchange_weak(
                 state,
-                state + 1,
+                state + permits,
                 Ordering::Release,
                 Ordering::Acquire,
             ) {
@@ -229,18 +1106,101 @@ impl PrioritySemaphore {
         }
     }
 
-    pub(crate) fn try_take(&self) -> Result<(), TryAcquireError> {
+    /// Returns permits granted in `band`, making room in the band before they
+    /// can reach a waiter.
+    pub(crate) fn release_from(&self, permits: usize, band: Option<&Band>) {
+        if let Some(band) = band {
+            band.discharge(permits);
+        }
+        self.release(permits);
+    }
+
+    /// Takes `permits` for `priority`, returning the capped band they were
+    /// charged to.
+    pub(crate) fn try_take(
+        &self,
+        permits: usize,
+        priority: &P,
+    ) -> Result<Option<Arc<Band>>, TryAcquireError> {
+        if self.capped.load(Ordering::Acquire) {
+            return self.try_take_capped(permits, priority);
+        }
+        // Permits beyond every reservation can be taken without the lock.
+        match self.try_take_above(permits, self.reserved.load(Ordering::Acquire)) {
+            Err(NoPermits) if self.reserved.load(Ordering::Acquire) != 0 => {
+                let queue = self.waiters.lock();
+                self.try_take_locked(&queue, permits, priority)
+            }
+            result => result,
+        }
+        .map(|()| None)
+    }
+
+    /// `try_take` while caps are installed. Finding and charging the band
+    /// needs the queue lock.
+    fn try_take_capped(
+        &self,
+        permits: usize,
+        priority: &P,
+    ) -> Result<Option<Arc<Band>>, TryAcquireError> {
+        let queue = self.waiters.lock();
+        let band = queue.band(priority);
+        if self.is_closed() {
+            return Err(Closed);
+        }
+        if band.is_some_and(|band| !band.has_room(permits)) {
+            return Err(NoPermits);
+        }
+        self.try_take_locked(&queue, permits, priority)?;
+        Ok(band.map(|band| {
+            band.charge(permits);
+            band.clone()
+        }))
+    }
+
+    /// Takes `permits` for `priority` with the queue lock held.
+    ///
+    /// Queued waiters that a reservation or a full cap holds back leave
+    /// permits in the pool, so a request may still take those as long as no
+    /// other waiter is short of returned permits.
+    fn try_take_locked(
+        &self,
+        queue: &WaitQueue<P>,
+        permits: usize,
+        priority: &P,
+    ) -> Result<(), TryAcquireError> {
+        let state = self.state.load(Ordering::Acquire);
+        if state & CLOSED != 0 {
+            return Err(Closed);
+        }
+        if state & PAUSED != 0 {
+            return Err(Paused);
+        }
+        if queue.only_held_back(state & PERMIT_MASK)
+            && self.take_pooled(permits, queue.headroom(priority))
+        {
+            Ok(())
+        } else {
+            Err(NoPermits)
+        }
+    }
+
+    /// Takes `permits` if at least `headroom` more remain available.
+    fn try_take_above(&self, permits: usize, headroom: usize) -> Result<(), TryAcquireError> {
         let mut state = self.state.load(Ordering::Acquire);
         loop {
             if state & CLOSED != 0 {
                 return Err(Closed);
             }
-            if state & HAS_WAITERS != 0 || state & PERMIT_MASK == 0 {
+            if state & PAUSED != 0 {
+                return Err(Paused);
+            }
+            if state & HAS_WAITERS != 0 || state & PERMIT_MASK < permits.saturating_add(headroom) {
                 return Err(NoPermits);
             }
             match self.state.compare_exchange_weak(
                 state,
-                state - 1,
+                state - permits,
                 Ordering::Acquire,
                 Ordering::Relaxed,
             ) {
@@ -250,54 +1210,267 @@ impl PrioritySemaphore {
         }
     }
 
-    fn release_slow(&self) {
+    /// Like `try_take`, but reports a failure that an
+    /// admission rule applies to as a rejection.
+    fn try_admit(
+        &self,
+        permits: usize,
+        priority: &P,
+    ) -> Result<Option<Arc<Band>>, TryAcquireError> {
+        match self.try_take(permits, priority) {
+            Err(NoPermits) => {
+                let queue = self.waiters.lock();
+                match queue.reject(priority, self.available_permits()) {
+                    Some(reason) => Err(Rejected(reason)),
+                    None => Err(NoPermits),
+                }
+            }
+            Err(Closed) => {
+                let queue = self.waiters.lock();
+                Err(queue.close_reason().cloned().map_or(Closed, ClosedWith))
+            }
+            result => result,
+        }
+    }
+
+    pub(crate) fn forget_held(&self, permits: usize, band: Option<&Band>) {
         let wake = {
             let mut queue = self.waiters.lock();
-            let state = self.state.load(Ordering::Acquire);
-            if state & CLOSED == 0 {
-                if let Some(entry) = queue.pop() {
-                    entry.waiter.assign();
-                    if queue.is_empty() {
-                        self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
-                    }
-                    Some(entry.waker)
-                } else {
-                    self.return_to_pool(&queue);
-                    None
+            // Permits a shrink is still waiting for were already taken off the
+            // capacity, so forgetting them only settles the debt.
+            let debt = self.debt.load(Ordering::Relaxed);
+            let paid = debt.min(permits);
+            if paid != 0 {
+                self.debt.store(debt - paid, Ordering::Relaxed);
+                if paid == debt {
+                    self.state.fetch_and(!SHRINKING, Ordering::Release);
                 }
-            } else {
-                // Close normally drained the queue before we could acquire the
-                // lock. Keep this branch defensive for unusual interleavings.
-                let entries = queue.drain();
-                for entry in &entries {
-                    entry.waiter.close();
-                }
-                self.return_to_pool(&queue);
-                drop(queue);
-                for entry in entries {
-                    entry.waker.wake();
+            }
+            let capacity = self.max_permits.load(Ordering::Relaxed);
+            self.max_permits
+                .store(capacity - (permits - paid), Ordering::Release);
+            if self.is_closed() {
+                if let Some(band) = band {
+                    band.discharge(permits);
                 }
+                // The forgotten permits may have been the last ones out.
+                let mut wake = WakeList::new();
+                self.wake_idle(&mut queue, &mut wake);
+                wake
+            } else {
+                let Some(band) = band else {
+                    return;
+                };
+                // The band has room again, which can unblock its waiters.
+                band.discharge(permits);
+                self.dispatch(&mut queue)
+            }
+        };
+        wake.wake_all();
+    }
+
+    fn release_slow(&self, permits: usize) {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            self.return_permits(permits, &mut queue)
+        };
+        wake.wake_all();
+    }
+
+    /// Returns permits with the queue lock held, settling pending capacity
+    /// reductions before anything reaches the pool.
+    fn return_permits(&self, permits: usize, queue: &mut WaitQueue<P>) -> WakeList {
+        let debt = self.debt.load(Ordering::Relaxed);
+        let paid = debt.min(permits);
+        if paid != 0 {
+            self.debt.store(debt - paid, Ordering::Relaxed);
+            if paid == debt {
+                self.state.fetch_and(!SHRINKING, Ordering::Release);
+            }
+        }
+        let permits = permits - paid;
+
+        // HAS_WAITERS keeps the fast path away from pooled permits, so they
+        // stay reserved for the queue until dispatch hands them off.
+        let previous = self.state.fetch_add(permits, Ordering::AcqRel);
+        debug_assert!(
+            (previous & PERMIT_MASK) + permits <= self.max_permits.load(Ordering::Relaxed)
+        );
+        if previous & CLOSED == 0 {
+            self.dispatch(queue)
+        } else {
+            // Close normally drained the queue before we could acquire the
+            // lock. Keep this branch defensive for unusual interleavings.
+            let entries = queue.drain();
+            let mut wake = WakeList::new();
+            let reason = queue.close_reason().cloned();
+            for entry in entries {
+                entry.waiter.close(reason.clone());
+                wake.push(entry.waker);
+            }
+            self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
+            self.wake_idle(queue, &mut wake);
+            wake
+        }
+    }
+
+    /// Raises the capacity with the queue lock held.
+    fn grow(&self, permits: usize, queue: &mut WaitQueue<P>) -> WakeList {
+        let capacity = self.max_permits.load(Ordering::Relaxed);
+        assert!(
+            permits <= PERMIT_MASK - capacity,
+            "too many semaphore permits"
+        );
+        self.max_permits
+            .store(capacity + permits, Ordering::Release);
+        self.return_permits(permits, queue)
+    }
+
+    /// Lowers the capacity with the queue lock held.
+    fn shrink(&self, permits: usize) {
+        let capacity = self.max_permits.load(Ordering::Relaxed);
+        let permits = permits.min(capacity);
+        if permits == 0 {
+            return;
+        }
+        self.max_permits
+            .store(capacity - permits, Ordering::Release);
+
+        // Divert releases to the slow path before draining the pool, so none
+        // can slip a permit back in after the pool has been counted.
+        self.state.fetch_or(SHRINKING, Ordering::AcqRel);
+        let mut state = self.state.load(Ordering::Acquire);
+        let taken = loop {
+            let taken = (state & PERMIT_MASK).min(permits);
+            match self.state.compare_exchange_weak(
+                state,
+                state - taken,
+                Ordering::AcqRel,
+                Ordering::Acquire,
+            ) {
+                Ok(_) => break taken,
+                Err(actual) => state = actual,
+            }
+        };
+        let debt = self.debt.load(Ordering::Relaxed) + (permits - taken);
+        self.debt.store(debt, Ordering::Relaxed);
+        if debt == 0 {
+            self.state.fetch_and(!SHRINKING, Ordering::Release);
+        }
+    }
+
+    /// Fails queued waiters from the head down while their deadline has
+    /// passed. Must be called with the queue lock held.
+    #[cfg(feature = "std")]
+    fn expire_heads(&self, queue: &mut WaitQueue<P>) -> WakeList {
+        let mut wake = WakeList::new();
+        while let Some(entry) = queue.pop_expired() {
+            entry.waiter.expire();
+            self.deadline_missed();
+            wake.push(entry.waker);
+        }
+        wake
+    }
+
+    /// Withdraws a queued waiter whose deadline has passed.
+    ///
+    /// Does nothing if the waiter was granted or failed in the meantime; its
+    /// status then tells the caller what happened.
+    #[cfg(feature = "std")]
+    pub(crate) fn expire_waiter(&self, key: WaitKey, waiter: &Waiter) {
+        let wake = {
+            let mut queue = self.waiters.lock();
+            if !waiter.is_waiting() {
                 return;
             }
+            queue.remove(key);
+            waiter.expire();
+            self.deadline_missed();
+            // Like a cancellation, this can unblock requests behind it.
+            self.dispatch(&mut queue)
         };
-        if let Some(waker) = wake {
-            waker.wake();
+        wake.wake_all();
+    }
+
+    /// Hands pooled permits to queued waiters, highest priority first.
+    ///
+    /// Must be called with the queue lock held. Whether a head request that
+    /// does not fit yet blocks the waiters behind it is decided by the
+    /// [`HeadOfLine`] policy.
+    fn dispatch(&self, queue: &mut WaitQueue<P>) -> WakeList {
+        let mut wake = WakeList::new();
+        // While paused, permits stay in the pool until `resume`.
+        if !self.is_paused() {
+            queue.refresh_aging();
+            while let Some((index, permits)) = queue.select(self.available_permits()) {
+                #[cfg(feature = "std")]
+                if let Some(entry) = queue.expire(index) {
+                    entry.waiter.expire();
+                    self.deadline_missed();
+                    wake.push(entry.waker);
+                    continue;
+                }
+                if !self.take_pooled(permits, queue.headroom_at(index)) {
+                    break;
+                }
+                let band = queue.band_at(index).cloned();
+                if let Some(band) = &band {
+                    band.charge(permits);
+                }
+                let entry = queue.take(index);
+                #[cfg(feature = "std")]
+                if entry.deadline.is_some() {
+                    self.deadline_met();
+                }
+                entry.waiter.assign(band);
+                wake.push(entry.waker);
+            }
+        }
+        if queue.is_empty() {
+            self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
+        }
+        wake
+    }
+
+    /// Evicts the lowest-ranked waiters while the queue is over its limit.
+    ///
+    /// Must be called with the queue lock held. Returns `true` if `arrival`,
+    /// a waiter that was just queued, was the one turned away.
+    fn shed(
+        &self,
+        queue: &mut WaitQueue<P>,
+        arrival: Option<WaitKey>,
+        wake: &mut WakeList,
+    ) -> bool {
+        let mut rejected = false;
+        while let Some(entry) = queue.pop_overflow() {
+            if Some(entry.key) == arrival {
+                rejected = true;
+            } else {
+                entry.waiter.evict();
+                wake.push(entry.waker);
+            }
         }
+        if queue.is_empty() {
+            self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
+        }
+        rejected
     }
 
-    fn return_to_pool(&self, queue: &WaitQueue) {
-        debug_assert!(queue.is_empty());
-        self.state.fetch_and(!HAS_WAITERS, Ordering::Release);
+    /// Removes `permits` from the pool if at least `headroom` more remain.
+    fn take_pooled(&self, permits: usize, headroom: usize) -> bool {
         let mut state = self.state.load(Ordering::Acquire);
         loop {
-            debug_assert!((state & PERMIT_MASK) < self.max_permits);
+            if state & PERMIT_MASK < permits.saturating_add(headroom) {
+                return false;
+            }
             match self.state.compare_exchange_weak(
                 state,
-                state + 1,
-                Ordering::Release,
+                state - permits,
+                Ordering::AcqRel,
                 Ordering::Acquire,
             ) {
-                Ok(_) => return,
+                Ok(_) => return true,
                 Err(actual) => state = actual,
             }
         }
@@ -0,0 +1,178 @@
+//! Runtime-agnostic timeouts for acquire futures.
+
+use crate::{error::AcquireError, permit::Permit, semaphore::Priority, waiter::AcquireFuture};
+use core::{
+    future::Future,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+#[cfg(feature = "std")]
+use std::{cmp::Reverse, time::Instant};
+
+/// Source of sleep futures used by
+/// [`PrioritySemaphore::acquire_timeout`](crate::PrioritySemaphore::acquire_timeout),
+/// [`PrioritySemaphore::acquire_until`](crate::PrioritySemaphore::acquire_until)
+/// and [`DeadlineSemaphore::acquire_by`](crate::DeadlineSemaphore::acquire_by).
+///
+/// The crate does not depend on a runtime, so a timer is supplied by the
+/// caller. Implementations for Tokio and async-io are available behind the
+/// `tokio` and `async-io` features.
+pub trait Timer {
+    /// Point in time accepted by [`Timer::sleep_until`].
+    type Instant;
+    /// Future that completes once the timer fires. Its output is ignored.
+    type Sleep: Future + Unpin;
+
+    /// Returns a future that completes after `duration`.
+    fn sleep(&self, duration: Duration) -> Self::Sleep;
+
+    /// Returns a future that completes at `deadline`.
+    fn sleep_until(&self, deadline: Self::Instant) -> Self::Sleep;
+}
+
+/// Future returned by
+/// [`PrioritySemaphore::acquire_timeout`](crate::PrioritySemaphore::acquire_timeout)
+/// and [`PrioritySemaphore::acquire_until`](crate::PrioritySemaphore::acquire_until).
+///
+/// Resolves with [`AcquireError::Timeout`] if the timer fires first. The
+/// acquisition is always polled before the timer, so a permit that has
+/// already been handed off wins; one handed off after the timer fired is
+/// passed on to the next waiter.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled or awaited"]
+pub struct AcquireTimeout<S, P: Ord = Priority> {
+    acquire: Option<AcquireFuture<P>>,
+    sleep: S,
+}
+
+impl<S, P: Ord> AcquireTimeout<S, P> {
+    pub(crate) fn new(acquire: AcquireFuture<P>, sleep: S) -> Self {
+        Self {
+            acquire: Some(acquire),
+            sleep,
+        }
+    }
+}
+
+impl<S: Future + Unpin, P: Ord> Future for AcquireTimeout<S, P> {
+    type Output = Result<Permit<P>, AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        let acquire = this
+            .acquire
+            .as_mut()
+            .expect("acquire future polled after completion");
+        if let Poll::Ready(result) = Pin::new(acquire).poll(cx) {
+            this.acquire = None;
+            return Poll::Ready(result);
+        }
+        if Pin::new(&mut this.sleep).poll(cx).is_ready() {
+            // Dropping the acquisition withdraws it from the queue, or passes
+            // on a permit assigned after the poll above.
+            this.acquire = None;
+            return Poll::Ready(Err(AcquireError::Timeout));
+        }
+        Poll::Pending
+    }
+}
+
+/// Future returned by
+/// [`DeadlineSemaphore::acquire_by`](crate::DeadlineSemaphore::acquire_by).
+///
+/// Resolves with [`AcquireError::DeadlineExpired`] once the deadline passes.
+/// The timer wakes the future at the deadline, and its firing counts as the
+/// deadline passing even if [`Instant::now`] has not caught up yet. As with
+/// [`AcquireTimeout`], a permit that has already been handed off wins.
+#[cfg(feature = "std")]
+#[cfg_attr(docsrs, doc(cfg(feature = "std")))]
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled or awaited"]
+pub struct AcquireBy<S> {
+    acquire: AcquireFuture<Reverse<Instant>>,
+    sleep: Option<S>,
+}
+
+#[cfg(feature = "std")]
+impl<S> AcquireBy<S> {
+    pub(crate) fn new(acquire: AcquireFuture<Reverse<Instant>>, sleep: S) -> Self {
+        Self {
+            acquire,
+            sleep: Some(sleep),
+        }
+    }
+
+    /// Changes the priority of this acquisition.
+    ///
+    /// See [`AcquireFuture::set_priority`]. The deadline it fails at stays
+    /// the same.
+    pub fn set_priority(&mut self, priority: Reverse<Instant>) {
+        self.acquire.set_priority(priority);
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: Future + Unpin> Future for AcquireBy<S> {
+    type Output = Result<Permit<Reverse<Instant>>, AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Poll::Ready(result) = Pin::new(&mut this.acquire).poll(cx) {
+            this.sleep = None;
+            return Poll::Ready(result);
+        }
+        if this
+            .sleep
+            .as_mut()
+            .is_some_and(|sleep| Pin::new(sleep).poll(cx).is_ready())
+        {
+            // The timer's clock decides; the acquisition then withdraws and
+            // counts the missed deadline, unless a permit beat it to it.
+            this.sleep = None;
+            this.acquire.expire_now();
+            return Pin::new(&mut this.acquire).poll(cx);
+        }
+        Poll::Pending
+    }
+}
+
+/// [`Timer`] backed by the Tokio time driver.
+#[cfg(feature = "tokio")]
+#[cfg_attr(docsrs, doc(cfg(feature = "tokio")))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioTimer;
+
+#[cfg(feature = "tokio")]
+impl Timer for TokioTimer {
+    type Instant = tokio::time::Instant;
+    type Sleep = Pin<alloc::boxed::Box<tokio::time::Sleep>>;
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        alloc::boxed::Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn sleep_until(&self, deadline: Self::Instant) -> Self::Sleep {
+        alloc::boxed::Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+/// [`Timer`] backed by `async-io`, usable with smol and async-std.
+#[cfg(feature = "async-io")]
+#[cfg_attr(docsrs, doc(cfg(feature = "async-io")))]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncIoTimer;
+
+#[cfg(feature = "async-io")]
+impl Timer for AsyncIoTimer {
+    type Instant = std::time::Instant;
+    type Sleep = async_io::Timer;
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        async_io::Timer::after(duration)
+    }
+
+    fn sleep_until(&self, deadline: Self::Instant) -> Self::Sleep {
+        async_io::Timer::at(deadline)
+    }
+}
@@ -1,157 +1,690 @@
 //! Acquire future and direct-handoff state.
 
 use crate::{
-    error::AcquireError,
-    permit::Permit,
-    queue::WaitKey,
+    error::{AcquireError, CloseReason},
+    lock::Lock,
+    permit::{Band, Permit, PermitRef},
+    queue::{Labels, WaitKey},
     semaphore::{Priority, PrioritySemaphore, RegisterResult},
 };
-use alloc::sync::Arc;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
 use core::{
     future::Future,
+    mem,
     pin::Pin,
     sync::atomic::{AtomicU8, Ordering},
-    task::{Context, Poll},
+    task::{Context, Poll, Waker},
 };
+#[cfg(feature = "std")]
+use std::time::Instant;
 
 const WAITING: u8 = 0;
 const ASSIGNED: u8 = 1;
 const CLOSED: u8 = 2;
+const EXPIRED: u8 = 3;
+const EVICTED: u8 = 4;
+const CANCELLED: u8 = 5;
 
 /// State shared between a queued future and the thread returning a permit.
 #[derive(Debug)]
-pub(crate) struct Waiter(AtomicU8);
+pub(crate) struct Waiter {
+    status: AtomicU8,
+    // The capped band the assigned permits were charged to, if any.
+    band: Lock<Option<Arc<Band>>>,
+    // The reason the semaphore was closed with when this waiter was failed.
+    reason: Lock<Option<CloseReason>>,
+}
 
 impl Waiter {
     pub(crate) const fn new() -> Self {
-        Self(AtomicU8::new(WAITING))
+        Self {
+            status: AtomicU8::new(WAITING),
+            band: Lock::new(None),
+            reason: Lock::new(None),
+        }
     }
 
-    pub(crate) fn assign(&self) {
-        self.0.store(ASSIGNED, Ordering::Release);
+    pub(crate) fn assign(&self, band: Option<Arc<Band>>) {
+        if band.is_some() {
+            *self.band.lock() = band;
+        }
+        self.status.store(ASSIGNED, Ordering::Release);
+    }
+
+    pub(crate) fn close(&self, reason: Option<CloseReason>) {
+        if reason.is_some() {
+            *self.reason.lock() = reason;
+        }
+        self.status.store(CLOSED, Ordering::Release);
     }
 
-    pub(crate) fn close(&self) {
-        self.0.store(CLOSED, Ordering::Release);
+    #[cfg(feature = "std")]
+    pub(crate) fn expire(&self) {
+        self.status.store(EXPIRED, Ordering::Release);
+    }
+
+    pub(crate) fn evict(&self) {
+        self.status.store(EVICTED, Ordering::Release);
+    }
+
+    pub(crate) fn cancel(&self) {
+        self.status.store(CANCELLED, Ordering::Release);
     }
 
     pub(crate) fn is_waiting(&self) -> bool {
-        self.0.load(Ordering::Acquire) == WAITING
+        self.status.load(Ordering::Acquire) == WAITING
     }
 
     pub(crate) fn is_assigned(&self) -> bool {
-        self.0.load(Ordering::Acquire) == ASSIGNED
+        self.status.load(Ordering::Acquire) == ASSIGNED
+    }
+
+    /// The error for a waiter failed by a close, with the reason as of then.
+    fn closed_error(&self) -> AcquireError {
+        self.reason
+            .lock()
+            .take()
+            .map_or(AcquireError::Closed, AcquireError::ClosedWith)
+    }
+
+    /// Takes the band charged for the assigned permits.
+    pub(crate) fn take_band(&self) -> Option<Arc<Band>> {
+        self.band.lock().take()
     }
 
     fn status(&self) -> u8 {
-        self.0.load(Ordering::Acquire)
+        self.status.load(Ordering::Acquire)
+    }
+}
+
+/// Wakers collected under the queue lock and invoked after it is released.
+///
+/// A single handoff is by far the most common case, so the first waker is
+/// stored inline and the vector only allocates for multi-permit releases.
+#[derive(Debug)]
+pub(crate) struct WakeList {
+    first: Option<Waker>,
+    rest: Vec<Waker>,
+}
+
+impl WakeList {
+    pub(crate) const fn new() -> Self {
+        Self {
+            first: None,
+            rest: Vec::new(),
+        }
+    }
+
+    pub(crate) fn push(&mut self, waker: Waker) {
+        if self.first.is_none() {
+            self.first = Some(waker);
+        } else {
+            self.rest.push(waker);
+        }
+    }
+
+    pub(crate) fn wake_all(self) {
+        if let Some(waker) = self.first {
+            waker.wake();
+        }
+        for waker in self.rest {
+            waker.wake();
+        }
     }
 }
 
 #[derive(Debug)]
-enum Phase {
-    Initial,
+enum Phase<P> {
+    // The priority moves into the queue once the acquisition is queued.
+    Initial(P),
     Waiting { key: WaitKey, waiter: Arc<Waiter> },
     Complete,
 }
 
-/// Future returned by [`PrioritySemaphore::acquire`](crate::PrioritySemaphore::acquire).
+/// Outcome of an acquisition: the capped band its permits were charged to,
+/// if any, or why it failed.
+type Granted = Result<Option<Arc<Band>>, AcquireError>;
+
+/// Registration state machine shared by the owned and borrowed futures.
+///
+/// It never stores the semaphore itself, so each future decides how the
+/// semaphore is held and what kind of permit a successful poll produces.
+#[derive(Debug)]
+struct Acquisition<P> {
+    permits: usize,
+    labels: Labels,
+    #[cfg(feature = "std")]
+    deadline: Option<Instant>,
+    phase: Phase<P>,
+}
+
+impl<P: Ord> Acquisition<P> {
+    const fn new(priority: P, permits: usize) -> Self {
+        Self {
+            permits,
+            labels: Labels {
+                tenant: None,
+                tag: None,
+            },
+            #[cfg(feature = "std")]
+            deadline: None,
+            phase: Phase::Initial(priority),
+        }
+    }
+
+    /// Completes with a permit, counting a met deadline if there is one.
+    fn grant(&mut self, root: &PrioritySemaphore<P>, band: Option<Arc<Band>>) -> Poll<Granted> {
+        #[cfg(feature = "std")]
+        if self.deadline.is_some() {
+            root.deadline_met();
+        }
+        #[cfg(not(feature = "std"))]
+        let _ = root;
+        self.finish(Ok(band))
+    }
+
+    fn finish(&mut self, result: Granted) -> Poll<Granted> {
+        self.phase = Phase::Complete;
+        Poll::Ready(result)
+    }
+
+    fn poll(&mut self, root: &PrioritySemaphore<P>, cx: &mut Context<'_>) -> Poll<Granted> {
+        match &self.phase {
+            #[cfg(feature = "std")]
+            Phase::Initial(_)
+                if self
+                    .deadline
+                    .is_some_and(|deadline| deadline <= Instant::now()) =>
+            {
+                root.deadline_missed();
+                self.finish(Err(AcquireError::DeadlineExpired))
+            }
+            Phase::Initial(priority) => match root.try_take(self.permits, priority) {
+                Ok(band) => self.grant(root, band),
+                Err(crate::TryAcquireError::Closed | crate::TryAcquireError::ClosedWith(_)) => {
+                    self.finish(Err(root.closed_error()))
+                }
+                Err(
+                    crate::TryAcquireError::NoPermits
+                    | crate::TryAcquireError::Rejected(_)
+                    | crate::TryAcquireError::Paused,
+                ) => {
+                    let Phase::Initial(priority) = mem::replace(&mut self.phase, Phase::Complete)
+                    else {
+                        unreachable!("acquisition left its initial phase");
+                    };
+                    match root.register(
+                        priority,
+                        self.permits,
+                        self.labels,
+                        #[cfg(feature = "std")]
+                        self.deadline,
+                        cx.waker(),
+                    ) {
+                        RegisterResult::Acquired(band) => self.grant(root, band),
+                        RegisterResult::Closed => self.finish(Err(root.closed_error())),
+                        RegisterResult::QueueFull => self.finish(Err(AcquireError::QueueFull)),
+                        RegisterResult::Rejected(reason) => {
+                            self.finish(Err(AcquireError::Rejected(reason)))
+                        }
+                        RegisterResult::Queued { key, waiter } => {
+                            // A multi-permit request can be granted by the
+                            // dispatch that runs right after it is queued.
+                            if waiter.is_assigned() {
+                                self.finish(Ok(waiter.take_band()))
+                            } else {
+                                self.phase = Phase::Waiting { key, waiter };
+                                Poll::Pending
+                            }
+                        }
+                    }
+                }
+            },
+            Phase::Waiting { key, waiter } => {
+                #[cfg(feature = "std")]
+                if self
+                    .deadline
+                    .is_some_and(|deadline| deadline <= Instant::now())
+                {
+                    root.expire_waiter(*key, waiter);
+                }
+                match waiter.status() {
+                    ASSIGNED => self.finish(Ok(waiter.take_band())),
+                    CLOSED => self.finish(Err(waiter.closed_error())),
+                    EXPIRED => self.finish(Err(AcquireError::DeadlineExpired)),
+                    EVICTED => self.finish(Err(AcquireError::Evicted)),
+                    CANCELLED => self.finish(Err(AcquireError::Cancelled)),
+                    WAITING => {
+                        root.refresh_waker(*key, waiter, cx.waker());
+                        // The status may have changed before refresh_waker took
+                        // the queue lock. In that case the corresponding wake is
+                        // already guaranteed, so Pending remains correct.
+                        Poll::Pending
+                    }
+                    _ => unreachable!("invalid waiter state"),
+                }
+            }
+            Phase::Complete => panic!("acquire future polled after completion"),
+        }
+    }
+
+    fn set_priority(&mut self, root: &PrioritySemaphore<P>, priority: P) {
+        match &mut self.phase {
+            Phase::Initial(current) => *current = priority,
+            Phase::Waiting { key, waiter } => {
+                root.reprioritize(*key, waiter, priority);
+            }
+            Phase::Complete => {}
+        }
+    }
+
+    fn cancel(&self, root: &PrioritySemaphore<P>) {
+        if let Phase::Waiting { key, waiter } = &self.phase {
+            root.cancel_waiter(*key, waiter, self.permits);
+        }
+    }
+}
+
+/// Parent stage of an acquisition on a child semaphore.
+///
+/// The child's permits are taken first and kept in `held` while the parent's
+/// are awaited. Dropping the stage returns them.
+#[derive(Debug)]
+struct Ascent<P: Ord, T> {
+    parent: AcquireFuture<P>,
+    held: Option<T>,
+    clone: fn(&P) -> P,
+}
+
+impl<P: Ord, T> Ascent<P, T> {
+    /// Prepares the parent stage if `root` is a child semaphore.
+    fn new(root: &PrioritySemaphore<P>, priority: &P, permits: usize) -> Option<Box<Self>> {
+        let (parent, clone) = root.parent.as_ref()?;
+        Some(Box::new(Self {
+            parent: AcquireFuture::new(parent.clone(), clone(priority), permits),
+            held: None,
+            clone: *clone,
+        }))
+    }
+
+    fn set_priority(&mut self, priority: &P) {
+        self.parent.set_priority((self.clone)(priority));
+    }
+
+    /// Polls for the parent's permits once the child's are held.
+    fn poll(&mut self, cx: &mut Context<'_>) -> Poll<Result<(T, Permit<P>), AcquireError>> {
+        match Pin::new(&mut self.parent).poll(cx) {
+            Poll::Ready(Ok(parent)) => {
+                let held = self.held.take().expect("child permits are held");
+                Poll::Ready(Ok((held, parent)))
+            }
+            Poll::Ready(Err(error)) => {
+                self.held = None;
+                Poll::Ready(Err(error))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+/// Future returned by [`PrioritySemaphore::acquire`](crate::PrioritySemaphore::acquire)
+/// and [`PrioritySemaphore::acquire_many`](crate::PrioritySemaphore::acquire_many).
 ///
 /// Dropping this future is cancellation-safe in every state, including after
 /// a permit has been assigned but before the executor polls it again.
 #[derive(Debug)]
 #[must_use = "futures do nothing unless polled or awaited"]
-pub struct AcquireFuture {
+pub struct AcquireFuture<P: Ord = Priority> {
     // Option lets a completed future move its existing Arc directly into the
     // permit instead of paying for an increment/decrement pair per acquire.
-    root: Option<Arc<PrioritySemaphore>>,
-    priority: Priority,
-    phase: Phase,
+    root: Option<Arc<PrioritySemaphore<P>>>,
+    inner: Acquisition<P>,
+    ascent: Option<Box<Ascent<P, Permit<P>>>>,
 }
 
-impl AcquireFuture {
-    pub(crate) fn new(root: Arc<PrioritySemaphore>, priority: Priority) -> Self {
+// The priority is never pinned; it is only moved into the queue.
+impl<P: Ord> Unpin for AcquireFuture<P> {}
+
+impl<P: Ord> AcquireFuture<P> {
+    pub(crate) fn new(root: Arc<PrioritySemaphore<P>>, priority: P, permits: usize) -> Self {
         Self {
+            ascent: Ascent::new(&root, &priority, permits),
             root: Some(root),
-            priority,
-            phase: Phase::Initial,
+            inner: Acquisition::new(priority, permits),
+        }
+    }
+
+    /// Queues the acquisition as part of `tenant`'s fair share.
+    pub(crate) fn for_tenant(mut self, tenant: u64, weight: u32) -> Self {
+        self.set_tenant(tenant, weight);
+        self
+    }
+
+    fn set_tenant(&mut self, tenant: u64, weight: u32) {
+        self.inner.labels.tenant = Some((tenant, weight));
+        if let Some(ascent) = &mut self.ascent {
+            ascent.parent.set_tenant(tenant, weight);
+        }
+    }
+
+    /// Files the acquisition under `tag` while it is queued, so
+    /// [`PrioritySemaphore::cancel_waiters_where`] can single it out.
+    ///
+    /// On a child semaphore, the parent's acquisition carries the tag as
+    /// well. An acquisition that is already queued keeps the tag it was
+    /// queued with.
+    pub fn with_tag(mut self, tag: u64) -> Self {
+        self.set_tag(tag);
+        self
+    }
+
+    fn set_tag(&mut self, tag: u64) {
+        self.inner.labels.tag = Some(tag);
+        if let Some(ascent) = &mut self.ascent {
+            ascent.parent.set_tag(tag);
+        }
+    }
+
+    /// Fails the acquisition with [`AcquireError::DeadlineExpired`] if
+    /// `deadline` passes before a permit is granted.
+    #[cfg(feature = "std")]
+    pub(crate) fn with_deadline(mut self, deadline: Instant) -> Self {
+        self.set_deadline(deadline);
+        self
+    }
+
+    /// Moves the deadline to now, so the next poll fails the acquisition
+    /// unless it has been granted.
+    #[cfg(feature = "std")]
+    pub(crate) fn expire_now(&mut self) {
+        self.set_deadline(Instant::now());
+    }
+
+    #[cfg(feature = "std")]
+    fn set_deadline(&mut self, deadline: Instant) {
+        self.inner.deadline = Some(deadline);
+        if let Some(ascent) = &mut self.ascent {
+            ascent.parent.set_deadline(deadline);
+        }
+    }
+
+    /// Changes the priority of this acquisition.
+    ///
+    /// A queued acquisition is moved within the queue in O(log n) and keeps
+    /// its arrival order relative to other waiters at the new priority. Before
+    /// the first poll, the new priority is simply used when it is queued. On a
+    /// child semaphore, the parent's acquisition is changed as well.
+    pub fn set_priority(&mut self, priority: P) {
+        if let Some(ascent) = &mut self.ascent {
+            ascent.set_priority(&priority);
+        }
+        if let Some(root) = &self.root {
+            self.inner.set_priority(root, priority);
+        }
+    }
+
+    /// Returns a handle that can change this acquisition's priority from
+    /// another task or thread.
+    ///
+    /// Returns `None` unless the future is currently queued, that is after it
+    /// has been polled once and before it completes. On a child semaphore,
+    /// the handle changes the priority at the level it is queued at.
+    pub fn priority_handle(&self) -> Option<PriorityHandle<P>> {
+        match (&self.root, &self.inner.phase) {
+            (Some(root), Phase::Waiting { key, waiter }) => Some(PriorityHandle {
+                root: root.clone(),
+                key: *key,
+                waiter: waiter.clone(),
+            }),
+            _ => self
+                .ascent
+                .as_ref()
+                .and_then(|ascent| ascent.parent.priority_handle()),
         }
     }
 }
 
-impl Future for AcquireFuture {
-    type Output = Result<Permit, AcquireError>;
+/// Future returned by
+/// [`PrioritySemaphore::acquire_preemptible`](crate::PrioritySemaphore::acquire_preemptible).
+///
+/// Dropping it is cancellation-safe in the same way as [`AcquireFuture`].
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled or awaited"]
+pub struct AcquirePreemptible<P: Ord = Priority> {
+    acquire: AcquireFuture<P>,
+    priority: Option<P>,
+}
+
+impl<P: Ord> Unpin for AcquirePreemptible<P> {}
+
+impl<P: Ord> AcquirePreemptible<P> {
+    pub(crate) fn new(acquire: AcquireFuture<P>, priority: P) -> Self {
+        Self {
+            acquire,
+            priority: Some(priority),
+        }
+    }
+}
+
+impl<P: Ord> Future for AcquirePreemptible<P> {
+    type Output = Result<Permit<P>, AcquireError>;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
         let this = self.get_mut();
-        match &this.phase {
-            Phase::Initial => match this.root.as_ref().unwrap().try_take() {
-                Ok(()) => {
-                    let root = this.root.take().unwrap();
-                    this.phase = Phase::Complete;
-                    Poll::Ready(Ok(Permit::new(root)))
-                }
-                Err(crate::TryAcquireError::Closed) => {
-                    this.root = None;
-                    this.phase = Phase::Complete;
-                    Poll::Ready(Err(AcquireError::Closed))
-                }
-                Err(crate::TryAcquireError::NoPermits) => {
-                    match this
-                        .root
-                        .as_ref()
-                        .unwrap()
-                        .register(this.priority, cx.waker())
-                    {
-                        RegisterResult::Acquired => {
-                            let root = this.root.take().unwrap();
-                            this.phase = Phase::Complete;
-                            Poll::Ready(Ok(Permit::new(root)))
-                        }
-                        RegisterResult::Closed => {
-                            this.root = None;
-                            this.phase = Phase::Complete;
-                            Poll::Ready(Err(AcquireError::Closed))
-                        }
-                        RegisterResult::Queued { key, waiter } => {
-                            this.phase = Phase::Waiting { key, waiter };
-                            Poll::Pending
-                        }
-                    }
-                }
-            },
-            Phase::Waiting { key, waiter } => match waiter.status() {
-                ASSIGNED => {
+        let permit = core::task::ready!(Pin::new(&mut this.acquire).poll(cx))?;
+        let priority = this
+            .priority
+            .take()
+            .expect("acquire future polled after completion");
+        Poll::Ready(Ok(permit.into_preemptible(priority)))
+    }
+}
+
+/// Future returned by
+/// [`PrioritySemaphore::closed_and_idle`](crate::PrioritySemaphore::closed_and_idle).
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled or awaited"]
+pub struct ClosedAndIdle<'a, P: Ord = Priority> {
+    root: &'a PrioritySemaphore<P>,
+    // Set while the future's waker is registered with the semaphore.
+    id: Option<u64>,
+}
+
+impl<'a, P: Ord> ClosedAndIdle<'a, P> {
+    pub(crate) fn new(root: &'a PrioritySemaphore<P>) -> Self {
+        Self { root, id: None }
+    }
+}
+
+impl<P: Ord> Future for ClosedAndIdle<'_, P> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let this = self.get_mut();
+        this.root.poll_idle(&mut this.id, cx)
+    }
+}
+
+impl<P: Ord> Drop for ClosedAndIdle<'_, P> {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            self.root.forget_idle(id);
+        }
+    }
+}
+
+/// Detached handle for re-prioritising a queued
+/// [`AcquireFuture`].
+///
+/// The handle does not keep the acquisition alive. Once it has been granted,
+/// cancelled or closed, [`PriorityHandle::set_priority`] has no effect.
+#[derive(Debug)]
+pub struct PriorityHandle<P: Ord = Priority> {
+    root: Arc<PrioritySemaphore<P>>,
+    key: WaitKey,
+    waiter: Arc<Waiter>,
+}
+
+impl<P: Ord> Clone for PriorityHandle<P> {
+    fn clone(&self) -> Self {
+        Self {
+            root: self.root.clone(),
+            key: self.key,
+            waiter: self.waiter.clone(),
+        }
+    }
+}
+
+impl<P: Ord> PriorityHandle<P> {
+    /// Changes the priority of the queued acquisition.
+    ///
+    /// Returns `false` if the acquisition is no longer queued.
+    pub fn set_priority(&self, priority: P) -> bool {
+        self.root.reprioritize(self.key, &self.waiter, priority)
+    }
+}
+
+impl<P: Ord> Future for AcquireFuture<P> {
+    type Output = Result<Permit<P>, AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if let Some(root) = &this.root {
+            let permit = match this.inner.poll(root, cx) {
+                Poll::Ready(Ok(band)) => {
                     let root = this.root.take().unwrap();
-                    this.phase = Phase::Complete;
-                    Poll::Ready(Ok(Permit::new(root)))
+                    Permit::new(root, this.inner.permits, band)
                 }
-                CLOSED => {
+                Poll::Ready(Err(error)) => {
                     this.root = None;
-                    this.phase = Phase::Complete;
-                    Poll::Ready(Err(AcquireError::Closed))
-                }
-                WAITING => {
-                    this.root
-                        .as_ref()
-                        .unwrap()
-                        .refresh_waker(*key, waiter, cx.waker());
-                    // The status may have changed before refresh_waker took
-                    // the queue lock. In that case the corresponding wake is
-                    // already guaranteed, so Pending remains correct.
-                    Poll::Pending
+                    this.ascent = None;
+                    return Poll::Ready(Err(error));
                 }
-                _ => unreachable!("invalid waiter state"),
-            },
-            Phase::Complete => panic!("AcquireFuture polled after completion"),
+                Poll::Pending => return Poll::Pending,
+            };
+            match &mut this.ascent {
+                Some(ascent) => ascent.held = Some(permit),
+                None => return Poll::Ready(Ok(permit)),
+            }
+        }
+        let ascent = this
+            .ascent
+            .as_mut()
+            .expect("acquire future polled after completion");
+        let result = ascent
+            .poll(cx)
+            .map_ok(|(permit, parent)| permit.with_parent(parent));
+        if result.is_ready() {
+            this.ascent = None;
         }
+        result
     }
 }
 
-impl Drop for AcquireFuture {
+impl<P: Ord> Drop for AcquireFuture<P> {
     fn drop(&mut self) {
-        if let (Some(root), Phase::Waiting { key, waiter }) = (&self.root, &self.phase) {
-            root.cancel_waiter(*key, waiter);
+        if let Some(root) = &self.root {
+            self.inner.cancel(root);
         }
     }
 }
+
+/// Future returned by [`PrioritySemaphore::acquire_ref`](crate::PrioritySemaphore::acquire_ref)
+/// and [`PrioritySemaphore::acquire_many_ref`](crate::PrioritySemaphore::acquire_many_ref).
+///
+/// This is the borrowing counterpart of [`AcquireFuture`] with the same
+/// cancellation guarantees.
+#[derive(Debug)]
+#[must_use = "futures do nothing unless polled or awaited"]
+pub struct AcquireRefFuture<'a, P: Ord = Priority> {
+    root: &'a PrioritySemaphore<P>,
+    inner: Acquisition<P>,
+    ascent: Option<Box<Ascent<P, PermitRef<'a, P>>>>,
+}
+
+impl<P: Ord> Unpin for AcquireRefFuture<'_, P> {}
+
+impl<'a, P: Ord> AcquireRefFuture<'a, P> {
+    pub(crate) fn new(root: &'a PrioritySemaphore<P>, priority: P, permits: usize) -> Self {
+        Self {
+            root,
+            ascent: Ascent::new(root, &priority, permits),
+            inner: Acquisition::new(priority, permits),
+        }
+    }
+
+    /// Acquires from `root` alone, leaving a child semaphore's parent to the
+    /// caller.
+    pub(crate) fn without_parent(
+        root: &'a PrioritySemaphore<P>,
+        priority: P,
+        permits: usize,
+    ) -> Self {
+        Self {
+            root,
+            ascent: None,
+            inner: Acquisition::new(priority, permits),
+        }
+    }
+
+    /// Files the acquisition under `tag` while it is queued.
+    ///
+    /// See [`AcquireFuture::with_tag`].
+    pub fn with_tag(mut self, tag: u64) -> Self {
+        self.inner.labels.tag = Some(tag);
+        if let Some(ascent) = &mut self.ascent {
+            ascent.parent.set_tag(tag);
+        }
+        self
+    }
+
+    /// Changes the priority of this acquisition.
+    ///
+    /// See [`AcquireFuture::set_priority`].
+    pub fn set_priority(&mut self, priority: P) {
+        if let Some(ascent) = &mut self.ascent {
+            ascent.set_priority(&priority);
+        }
+        self.inner.set_priority(self.root, priority);
+    }
+}
+
+impl<'a, P: Ord> Future for AcquireRefFuture<'a, P> {
+    type Output = Result<PermitRef<'a, P>, AcquireError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.get_mut();
+        if this
+            .ascent
+            .as_ref()
+            .is_none_or(|ascent| ascent.held.is_none())
+        {
+            let permit = match this.inner.poll(this.root, cx) {
+                Poll::Ready(Ok(band)) => PermitRef::new(this.root, this.inner.permits, band),
+                Poll::Ready(Err(error)) => {
+                    this.ascent = None;
+                    return Poll::Ready(Err(error));
+                }
+                Poll::Pending => return Poll::Pending,
+            };
+            match &mut this.ascent {
+                Some(ascent) => ascent.held = Some(permit),
+                None => return Poll::Ready(Ok(permit)),
+            }
+        }
+        let ascent = this.ascent.as_mut().unwrap();
+        let result = ascent
+            .poll(cx)
+            .map_ok(|(permit, parent)| permit.with_parent(parent));
+        if result.is_ready() {
+            this.ascent = None;
+        }
+        result
+    }
+}
+
+impl<P: Ord> Drop for AcquireRefFuture<'_, P> {
+    fn drop(&mut self) {
+        self.inner.cancel(self.root);
+    }
+}
@@ -1,4 +1,6 @@
-use priority_semaphore::PrioritySemaphore;
+use priority_semaphore::{
+    AcquireError, Cap, PrioritySemaphore, Reservation, TryAcquireError, acquire_all, acquire_any,
+};
 use std::sync::{
     Arc,
     atomic::{AtomicUsize, Ordering},
@@ -63,6 +65,697 @@ async fn heavy_multithreaded_churn_never_exceeds_capacity() {
     .expect("multithreaded churn deadlocked");
 }
 
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn weighted_churn_never_exceeds_capacity() {
+    const PERMITS: usize = 8;
+    const TASKS: usize = 64;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphore = Arc::new(PrioritySemaphore::new(PERMITS));
+        let active = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let semaphore = semaphore.clone();
+            let active = active.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let weight = (task_id + iteration) % PERMITS + 1;
+                    let priority = ((task_id * 7 + iteration * 13) % 21) as i32 - 10;
+                    let permit = semaphore.acquire_many(weight, priority).await.unwrap();
+                    let now = active.fetch_add(weight, Ordering::SeqCst) + weight;
+                    assert!(now <= PERMITS, "capacity exceeded: {now} > {PERMITS}");
+                    tokio::task::yield_now().await;
+                    active.fetch_sub(weight, Ordering::SeqCst);
+                    drop(permit);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(semaphore.available_permits(), PERMITS);
+        assert_eq!(semaphore.queued(), 0);
+    })
+    .await
+    .expect("weighted churn deadlocked");
+}
+
+#[cfg(feature = "std")]
+#[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+async fn blocking_threads_and_async_tasks_share_capacity() {
+    const PERMITS: usize = 3;
+    const THREADS: usize = 4;
+    const TASKS: usize = 32;
+    const ACQUIRES: usize = 200;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphore = Arc::new(PrioritySemaphore::new(PERMITS));
+        let active = Arc::new(AtomicUsize::new(0));
+
+        let threads: Vec<_> = (0..THREADS)
+            .map(|thread_id| {
+                let semaphore = semaphore.clone();
+                let active = active.clone();
+                std::thread::spawn(move || {
+                    for iteration in 0..ACQUIRES {
+                        let priority = ((thread_id + iteration) % 5) as i32;
+                        let timeout = Duration::from_micros((iteration % 7) as u64 * 50);
+                        let permit = if iteration % 4 == 0 {
+                            match semaphore.acquire_blocking_timeout(priority, timeout) {
+                                Ok(permit) => permit,
+                                Err(_) => continue,
+                            }
+                        } else {
+                            semaphore.acquire_blocking(priority).unwrap()
+                        };
+                        let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                        assert!(now <= PERMITS, "capacity exceeded: {now} > {PERMITS}");
+                        active.fetch_sub(1, Ordering::SeqCst);
+                        drop(permit);
+                    }
+                })
+            })
+            .collect();
+
+        let mut tasks = Vec::with_capacity(TASKS);
+        for task_id in 0..TASKS {
+            let semaphore = semaphore.clone();
+            let active = active.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES / 4 {
+                    let priority = ((task_id * 3 + iteration) % 5) as i32;
+                    let permit = semaphore.acquire(priority).await.unwrap();
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    assert!(now <= PERMITS, "capacity exceeded: {now} > {PERMITS}");
+                    tokio::task::yield_now().await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    drop(permit);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        tokio::task::spawn_blocking(move || {
+            for thread in threads {
+                thread.join().unwrap();
+            }
+        })
+        .await
+        .unwrap();
+        assert_eq!(semaphore.available_permits(), PERMITS);
+        assert_eq!(semaphore.queued(), 0);
+    })
+    .await
+    .expect("blocking and async waiters deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn resizing_under_churn_never_exceeds_capacity() {
+    const MAX: usize = 8;
+    const TASKS: usize = 64;
+    const ACQUIRES_PER_TASK: usize = 200;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphore = Arc::new(PrioritySemaphore::new(MAX));
+        let active = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::with_capacity(TASKS);
+        for task_id in 0..TASKS {
+            let semaphore = semaphore.clone();
+            let active = active.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let priority = ((task_id + iteration) % 11) as i32;
+                    let permit = semaphore.acquire(priority).await.unwrap();
+                    let now = active.fetch_add(1, Ordering::SeqCst) + 1;
+                    assert!(now <= MAX, "capacity exceeded: {now} > {MAX}");
+                    tokio::task::yield_now().await;
+                    active.fetch_sub(1, Ordering::SeqCst);
+                    drop(permit);
+                }
+            }));
+        }
+
+        let resizer = {
+            let semaphore = semaphore.clone();
+            tokio::spawn(async move {
+                for round in 0..500 {
+                    semaphore.resize(round % MAX + 1);
+                    tokio::task::yield_now().await;
+                }
+                semaphore.resize(MAX);
+            })
+        };
+
+        resizer.await.unwrap();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(semaphore.max_permits(), MAX);
+        assert_eq!(semaphore.available_permits(), MAX);
+        assert_eq!(semaphore.queued(), 0);
+    })
+    .await
+    .expect("resizing under churn deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn lower_bands_never_take_reserved_permits_under_churn() {
+    const PERMITS: usize = 6;
+    const RESERVED: usize = 2;
+    const TASKS: usize = 96;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphore = Arc::new(PrioritySemaphore::new(PERMITS));
+        semaphore.set_reservations([Reservation::new(100, RESERVED)]);
+        let bulk_active = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let semaphore = semaphore.clone();
+            let bulk_active = bulk_active.clone();
+            tasks.push(tokio::spawn(async move {
+                let control = task_id % 8 == 0;
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let priority = if control {
+                        100
+                    } else {
+                        ((task_id * 13 + iteration * 7) % 100) as i32 - 50
+                    };
+                    let permit = if iteration % 4 == 0 {
+                        match semaphore.try_acquire(priority) {
+                            Ok(permit) => permit,
+                            Err(_) => continue,
+                        }
+                    } else {
+                        semaphore.acquire(priority).await.unwrap()
+                    };
+                    if !control {
+                        let now = bulk_active.fetch_add(1, Ordering::SeqCst) + 1;
+                        assert!(now <= PERMITS - RESERVED, "reservation taken: {now}");
+                    }
+                    tokio::task::yield_now().await;
+                    if !control {
+                        bulk_active.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    drop(permit);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(semaphore.available_permits(), PERMITS);
+        assert_eq!(semaphore.queued(), 0);
+    })
+    .await
+    .expect("reserved churn deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn capped_bands_never_exceed_their_cap_under_churn() {
+    const PERMITS: usize = 6;
+    const CAP: usize = 2;
+    const TASKS: usize = 96;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphore = Arc::new(PrioritySemaphore::new(PERMITS));
+        semaphore.set_caps([Cap::new(0, CAP)]);
+        let batch_active = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let semaphore = semaphore.clone();
+            let batch_active = batch_active.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let priority = ((task_id * 13 + iteration * 7) % 100) as i32 - 50;
+                    let permit = match iteration % 4 {
+                        0 => match semaphore.try_acquire(priority) {
+                            Ok(permit) => permit,
+                            Err(_) => continue,
+                        },
+                        1 => {
+                            // Cancelled acquisitions may already hold a
+                            // charged permit, which must be returned to
+                            // the band.
+                            let acquire = semaphore.acquire(priority);
+                            match tokio::time::timeout(Duration::from_micros(50), acquire).await {
+                                Ok(permit) => permit.unwrap(),
+                                Err(_) => continue,
+                            }
+                        }
+                        _ => semaphore.acquire(priority).await.unwrap(),
+                    };
+                    let batch = priority < 0;
+                    if batch {
+                        let now = batch_active.fetch_add(1, Ordering::SeqCst) + 1;
+                        assert!(now <= CAP, "cap exceeded: {now}");
+                    }
+                    tokio::task::yield_now().await;
+                    if batch {
+                        batch_active.fetch_sub(1, Ordering::SeqCst);
+                    }
+                    drop(permit);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(semaphore.available_permits(), PERMITS);
+        assert_eq!(semaphore.queued(), 0);
+        // Every charged permit was returned, so the whole cap is usable.
+        let batch = semaphore.try_acquire_many(CAP, -1).unwrap();
+        drop(batch);
+    })
+    .await
+    .expect("capped churn deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn tenant_churn_with_cancellation_preserves_every_permit() {
+    const PERMITS: usize = 4;
+    const TASKS: usize = 96;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphore = Arc::new(PrioritySemaphore::new(PERMITS));
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let semaphore = semaphore.clone();
+            tasks.push(tokio::spawn(async move {
+                let tenant = (task_id % 6) as u64;
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let priority = ((task_id * 7 + iteration * 3) % 10) as i32;
+                    let acquire = if task_id % 6 == 0 {
+                        semaphore.acquire(priority)
+                    } else {
+                        semaphore.acquire_for(tenant, tenant as u32, priority)
+                    };
+                    if iteration % 3 == 0 {
+                        if let Ok(permit) =
+                            tokio::time::timeout(Duration::from_micros(50), acquire).await
+                        {
+                            drop(permit.unwrap());
+                        }
+                        continue;
+                    }
+                    let permit = acquire.await.unwrap();
+                    tokio::task::yield_now().await;
+                    drop(permit);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(semaphore.available_permits(), PERMITS);
+        assert_eq!(semaphore.queued(), 0);
+    })
+    .await
+    .expect("tenant churn deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn interleaved_acquisitions_across_a_semaphore_tree_never_deadlock() {
+    const TASKS: usize = 96;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let cluster = Arc::new(PrioritySemaphore::new(4));
+        let services = [cluster.child(3), cluster.child(3)];
+        let endpoints: Vec<_> = services
+            .iter()
+            .flat_map(|service| [service.child(1), service.child(2)])
+            .collect();
+        let levels: Vec<_> = [cluster.clone()]
+            .into_iter()
+            .chain(services.iter().cloned())
+            .chain(endpoints.iter().cloned())
+            .collect();
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let levels = levels.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let semaphore = &levels[(task_id + iteration) % levels.len()];
+                    let priority = ((task_id * 7 + iteration * 3) % 10) as i32;
+                    if iteration % 3 == 0 {
+                        let acquire = semaphore.acquire(priority);
+                        if let Ok(permit) =
+                            tokio::time::timeout(Duration::from_micros(50), acquire).await
+                        {
+                            drop(permit.unwrap());
+                        }
+                        continue;
+                    }
+                    let permit = semaphore.acquire(priority).await.unwrap();
+                    tokio::task::yield_now().await;
+                    drop(permit);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        for semaphore in &levels {
+            assert_eq!(semaphore.available_permits(), semaphore.max_permits());
+            assert_eq!(semaphore.queued(), 0);
+        }
+    })
+    .await
+    .expect("semaphore tree deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn overlapping_acquire_all_sets_never_deadlock() {
+    const TASKS: usize = 96;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphores: Arc<[PrioritySemaphore; 4]> = Arc::new([
+            PrioritySemaphore::new(1),
+            PrioritySemaphore::new(2),
+            PrioritySemaphore::new(1),
+            PrioritySemaphore::new(3),
+        ]);
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let semaphores = semaphores.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let first = &semaphores[(task_id + iteration) % 4];
+                    let second = &semaphores[(task_id * 3 + iteration + 1) % 4];
+                    let priority = ((task_id * 7 + iteration * 3) % 10) as i32;
+                    let acquire = acquire_all(&[second, first], priority);
+                    if iteration % 3 == 0 {
+                        if let Ok(permits) =
+                            tokio::time::timeout(Duration::from_micros(50), acquire).await
+                        {
+                            drop(permits.unwrap());
+                        }
+                        continue;
+                    }
+                    let permits = acquire.await.unwrap();
+                    tokio::task::yield_now().await;
+                    drop(permits);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        for semaphore in semaphores.iter() {
+            assert_eq!(semaphore.available_permits(), semaphore.max_permits());
+            assert_eq!(semaphore.queued(), 0);
+        }
+    })
+    .await
+    .expect("overlapping acquire_all deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn acquire_all_across_a_tree_never_deadlocks() {
+    const TASKS: usize = 96;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let parent = Arc::new(PrioritySemaphore::new(1));
+        let child = parent.child(1);
+        let other = Arc::new(PrioritySemaphore::new(1));
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let (parent, child, other) = (parent.clone(), child.clone(), other.clone());
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let priority = ((task_id * 7 + iteration * 3) % 10) as i32;
+                    // Plain child acquisitions climb to the parent on their
+                    // own, so these three shapes could form a cycle.
+                    match (task_id + iteration) % 4 {
+                        0 => drop(child.acquire(priority).await.unwrap()),
+                        1 => drop(acquire_all(&[&*parent, &*other], priority).await.unwrap()),
+                        2 => drop(acquire_all(&[&*other, &*child], priority).await.unwrap()),
+                        _ => drop(acquire_all(&[&*child, &*parent], priority).await.unwrap()),
+                    }
+                    tokio::task::yield_now().await;
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        for semaphore in [&parent, &child, &other] {
+            assert_eq!(semaphore.available_permits(), semaphore.max_permits());
+            assert_eq!(semaphore.queued(), 0);
+        }
+    })
+    .await
+    .expect("acquire_all across a tree deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn racing_acquire_any_preserves_every_permit() {
+    const TASKS: usize = 96;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let replicas: Arc<[_]> = (1..=3)
+            .map(|permits| Arc::new(PrioritySemaphore::new(permits)))
+            .collect();
+        let held = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let replicas = replicas.clone();
+            let held = held.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let priority = ((task_id * 7 + iteration * 3) % 10) as i32;
+                    let acquire = acquire_any(&replicas, priority);
+                    if iteration % 3 == 0 {
+                        if let Ok(granted) =
+                            tokio::time::timeout(Duration::from_micros(50), acquire).await
+                        {
+                            drop(granted.unwrap());
+                        }
+                        continue;
+                    }
+                    let (_, permit) = acquire.await.unwrap();
+                    assert!(held.fetch_add(1, Ordering::SeqCst) < 6);
+                    tokio::task::yield_now().await;
+                    held.fetch_sub(1, Ordering::SeqCst);
+                    drop(permit);
+                }
+            }));
+        }
+
+        for task in tasks {
+            task.await.unwrap();
+        }
+        for replica in replicas.iter() {
+            assert_eq!(replica.available_permits(), replica.max_permits());
+            assert_eq!(replica.queued(), 0);
+        }
+    })
+    .await
+    .expect("acquire_any churn deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn preemptible_holders_yield_to_urgent_work_under_churn() {
+    const BATCH_TASKS: usize = 32;
+    const URGENT_TASKS: usize = 16;
+    const ACQUIRES_PER_TASK: usize = 30;
+
+    let semaphore = Arc::new(PrioritySemaphore::new(4));
+    let revoked = Arc::new(AtomicUsize::new(0));
+    let mut tasks = Vec::with_capacity(BATCH_TASKS + URGENT_TASKS);
+
+    for task_id in 0..BATCH_TASKS {
+        let semaphore = semaphore.clone();
+        let revoked = revoked.clone();
+        tasks.push(tokio::spawn(async move {
+            for iteration in 0..ACQUIRES_PER_TASK {
+                let priority = ((task_id + iteration) % 3) as i32;
+                let permit = semaphore.acquire_preemptible(priority).await.unwrap();
+                // Batch work runs until it is done or asked to stop.
+                if tokio::time::timeout(Duration::from_millis(2), permit.revoked())
+                    .await
+                    .is_ok()
+                {
+                    assert!(permit.is_revoked());
+                    revoked.fetch_add(1, Ordering::Relaxed);
+                }
+                drop(permit);
+            }
+        }));
+    }
+    for _ in 0..URGENT_TASKS {
+        let semaphore = semaphore.clone();
+        tasks.push(tokio::spawn(async move {
+            for _ in 0..ACQUIRES_PER_TASK {
+                let acquire = semaphore.acquire(1000);
+                let permit = tokio::time::timeout(Duration::from_secs(10), acquire)
+                    .await
+                    .expect("urgent work was not let through")
+                    .unwrap();
+                tokio::task::yield_now().await;
+                drop(permit);
+            }
+        }));
+    }
+
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert!(revoked.load(Ordering::Relaxed) > 0);
+    assert_eq!(semaphore.available_permits(), 4);
+    assert_eq!(semaphore.queued(), 0);
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn closed_and_idle_resolves_after_churn_is_shut_down() {
+    const TASKS: usize = 64;
+
+    let semaphore = Arc::new(PrioritySemaphore::new(4));
+    let mut tasks = Vec::with_capacity(TASKS);
+    for task_id in 0..TASKS {
+        let semaphore = semaphore.clone();
+        tasks.push(tokio::spawn(async move {
+            let priority = (task_id % 10) as i32;
+            while let Ok(permit) = semaphore.acquire(priority).await {
+                tokio::task::yield_now().await;
+                drop(permit);
+            }
+        }));
+    }
+
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    semaphore.close();
+    tokio::time::timeout(Duration::from_secs(10), semaphore.closed_and_idle())
+        .await
+        .expect("closed semaphore never became idle");
+    assert_eq!(semaphore.available_permits(), 4);
+    for task in tasks {
+        task.await.unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn pausing_under_churn_never_grants_or_loses_permits() {
+    const TASKS: usize = 64;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphore = Arc::new(PrioritySemaphore::new(4));
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let semaphore = semaphore.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let priority = ((task_id * 7 + iteration * 3) % 10) as i32;
+                    let permit = match semaphore.try_acquire(priority) {
+                        Ok(permit) => permit,
+                        Err(_) => semaphore.acquire(priority).await.unwrap(),
+                    };
+                    tokio::task::yield_now().await;
+                    drop(permit);
+                }
+            }));
+        }
+
+        let toggler = {
+            let semaphore = semaphore.clone();
+            tokio::spawn(async move {
+                for _ in 0..200 {
+                    semaphore.pause();
+                    assert_eq!(
+                        semaphore.try_acquire(i32::MAX).unwrap_err(),
+                        TryAcquireError::Paused
+                    );
+                    tokio::task::yield_now().await;
+                    semaphore.resume();
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        toggler.await.unwrap();
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert_eq!(semaphore.available_permits(), 4);
+        assert_eq!(semaphore.queued(), 0);
+    })
+    .await
+    .expect("pause and resume churn deadlocked");
+}
+
+#[tokio::test(flavor = "multi_thread", worker_threads = 8)]
+async fn selective_cancellation_under_churn_preserves_every_permit() {
+    const TASKS: usize = 64;
+    const ACQUIRES_PER_TASK: usize = 100;
+
+    tokio::time::timeout(Duration::from_secs(30), async {
+        let semaphore = Arc::new(PrioritySemaphore::new(3));
+        let cancelled = Arc::new(AtomicUsize::new(0));
+        let mut tasks = Vec::with_capacity(TASKS);
+
+        for task_id in 0..TASKS {
+            let semaphore = semaphore.clone();
+            let cancelled = cancelled.clone();
+            tasks.push(tokio::spawn(async move {
+                for iteration in 0..ACQUIRES_PER_TASK {
+                    let priority = ((task_id * 7 + iteration * 3) % 10) as i32;
+                    let permits = 1 + iteration % 2;
+                    match semaphore.acquire_many(permits, priority).await {
+                        Ok(permit) => {
+                            tokio::task::yield_now().await;
+                            drop(permit);
+                        }
+                        Err(error) => {
+                            assert_eq!(error, AcquireError::Cancelled);
+                            assert!(priority < 5);
+                            cancelled.fetch_add(1, Ordering::Relaxed);
+                        }
+                    }
+                }
+            }));
+        }
+
+        while tasks.iter().any(|task| !task.is_finished()) {
+            semaphore.cancel_below(5);
+            tokio::time::sleep(Duration::from_micros(100)).await;
+        }
+        for task in tasks {
+            task.await.unwrap();
+        }
+        assert!(cancelled.load(Ordering::Relaxed) > 0);
+        assert_eq!(semaphore.available_permits(), 3);
+        assert_eq!(semaphore.queued(), 0);
+    })
+    .await
+    .expect("selective cancellation churn deadlocked");
+}
+
 #[tokio::test(flavor = "multi_thread", worker_threads = 8)]
 async fn mass_cancellation_preserves_every_permit() {
     const WAITERS: usize = 4_096;
@@ -1,9 +1,22 @@
-use priority_semaphore::{AcquireError, AcquireFuture, Permit, PrioritySemaphore, TryAcquireError};
+#[cfg(feature = "std")]
+use priority_semaphore::DeadlineSemaphore;
+use priority_semaphore::{
+    AcquireError, AcquireFuture, AcquirePreemptible, AcquireRefFuture, AdmissionRule, Aging, Busy,
+    Cap, EarliestDeadlineFirst, HeadOfLine, Permit, PermitRef, PriorityHandle, PriorityLifo,
+    PrioritySemaphore, Reservation, RoundRobin, SchedulingPolicy, Timer, TryAcquireError,
+    WaiterInfo, acquire_all, acquire_any,
+};
+use std::cmp::Reverse;
 use std::time::Duration;
+#[cfg(feature = "std")]
+use std::time::Instant;
 use std::{
     future::Future,
     pin::Pin,
-    sync::Arc,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
     task::{Context, Poll, Waker},
 };
 use tokio::sync::{mpsc, oneshot};
@@ -179,6 +192,1585 @@ async fn a_permit_assigned_before_close_still_succeeds() {
     assert_eq!(semaphore.available_permits(), 1);
 }
 
+#[tokio::test]
+async fn weighted_request_accumulates_returned_permits_without_stealing() {
+    let semaphore = Arc::new(PrioritySemaphore::new(4));
+    let three = semaphore.try_acquire_many(3, 0).unwrap();
+    let one = semaphore.try_acquire(0).unwrap();
+    let mut weighted = Box::pin(semaphore.acquire_many(4, 0));
+    assert!(poll_once(weighted.as_mut()).is_pending());
+
+    drop(three);
+    assert_eq!(semaphore.available_permits(), 3);
+    assert_eq!(
+        semaphore.try_acquire(i32::MAX).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    assert!(poll_once(weighted.as_mut()).is_pending());
+
+    drop(one);
+    let permit = weighted.await.unwrap();
+    assert_eq!(permit.num_permits(), 4);
+    assert_eq!(semaphore.available_permits(), 0);
+    drop(permit);
+    assert_eq!(semaphore.available_permits(), 4);
+}
+
+#[tokio::test]
+async fn higher_priority_small_request_waits_for_permits_pooled_for_a_larger_one() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    let one = semaphore.try_acquire(0).unwrap();
+    let two = semaphore.try_acquire_many(2, 0).unwrap();
+    let mut large = Box::pin(semaphore.acquire_many(3, 5));
+    assert!(poll_once(large.as_mut()).is_pending());
+
+    // The pooled permit stays with the larger request; the new queue head
+    // is served from the next permit that is returned.
+    drop(one);
+    let mut small = Box::pin(semaphore.acquire(10));
+    assert!(poll_once(small.as_mut()).is_pending());
+    drop(two);
+    let small = match poll_once(small.as_mut()) {
+        Poll::Ready(permit) => permit.unwrap(),
+        Poll::Pending => panic!("the new queue head should use the returned permits"),
+    };
+    assert!(poll_once(large.as_mut()).is_pending());
+
+    drop(small);
+    assert_eq!(large.await.unwrap().num_permits(), 3);
+    assert_eq!(semaphore.available_permits(), 3);
+}
+
+#[tokio::test]
+async fn cancelling_a_blocked_weighted_head_unblocks_smaller_waiters() {
+    let semaphore = Arc::new(PrioritySemaphore::new(2));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut large = Box::pin(semaphore.acquire_many(2, 10));
+    let mut small = Box::pin(semaphore.acquire(1));
+    assert!(poll_once(large.as_mut()).is_pending());
+    assert!(poll_once(small.as_mut()).is_pending());
+    assert_eq!(semaphore.available_permits(), 1);
+
+    drop(large);
+    let small = small.await.unwrap();
+    assert_eq!(semaphore.available_permits(), 0);
+    drop((gate, small));
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[tokio::test]
+async fn skip_ahead_lets_small_requests_use_permits_the_head_cannot() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    let gate = semaphore.try_acquire_many(2, 0).unwrap();
+    let mut large = Box::pin(semaphore.acquire_many(3, 10));
+    let mut small = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(large.as_mut()).is_pending());
+    assert!(poll_once(small.as_mut()).is_pending());
+    assert_eq!(semaphore.head_of_line(), HeadOfLine::Strict);
+
+    // Switching policy grants the blocked small request straight away.
+    semaphore.set_head_of_line(HeadOfLine::SkipAhead);
+    let small = small.await.unwrap();
+    assert_eq!(semaphore.queued(), 1);
+
+    let mut next = Box::pin(semaphore.acquire(-5));
+    assert!(poll_once(next.as_mut()).is_pending());
+    drop(small);
+    drop(next.await.unwrap());
+    drop(gate);
+    assert_eq!(large.await.unwrap().num_permits(), 3);
+}
+
+#[cfg(feature = "std")]
+#[tokio::test]
+async fn bounded_skip_ahead_stops_once_the_head_has_waited_long_enough() {
+    let semaphore = Arc::new(PrioritySemaphore::new(2));
+    semaphore.set_head_of_line(HeadOfLine::BoundedSkipAhead(Duration::from_millis(50)));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut large = Box::pin(semaphore.acquire_many(2, 10));
+    assert!(poll_once(large.as_mut()).is_pending());
+
+    let mut early = Box::pin(semaphore.acquire(0));
+    let early = match poll_once(early.as_mut()) {
+        Poll::Ready(permit) => permit.unwrap(),
+        Poll::Pending => panic!("a young head should let smaller requests skip ahead"),
+    };
+    drop(early);
+
+    tokio::time::sleep(Duration::from_millis(60)).await;
+    let mut late = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(late.as_mut()).is_pending());
+    drop(gate);
+    let large_permit = large.await.unwrap();
+    assert!(poll_once(late.as_mut()).is_pending());
+    drop(large_permit);
+    drop(late.await.unwrap());
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[tokio::test]
+async fn borrowed_permits_work_with_a_static_semaphore() {
+    static SEMAPHORE: PrioritySemaphore = PrioritySemaphore::new(1);
+
+    let gate = SEMAPHORE.acquire_ref(0).await.unwrap();
+    let (tx, mut rx) = mpsc::unbounded_channel();
+    let mut tasks = Vec::new();
+    for (index, priority) in [1, 10].into_iter().enumerate() {
+        let tx = tx.clone();
+        tasks.push(tokio::spawn(async move {
+            let _permit = SEMAPHORE.acquire_ref(priority).await.unwrap();
+            tx.send(priority).unwrap();
+        }));
+        wait_for_queue(&SEMAPHORE, index + 1).await;
+    }
+    drop(tx);
+    assert_eq!(
+        SEMAPHORE.try_acquire_ref(0).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    drop(gate);
+
+    let mut actual = Vec::new();
+    while let Some(priority) = rx.recv().await {
+        actual.push(priority);
+    }
+    for task in tasks {
+        task.await.unwrap();
+    }
+    assert_eq!(actual, [10, 1]);
+    assert_eq!(SEMAPHORE.available_permits(), 1);
+}
+
+#[tokio::test]
+async fn cancelling_a_borrowed_acquire_after_handoff_returns_the_permit() {
+    let semaphore = PrioritySemaphore::new(2);
+    let gate = semaphore.try_acquire_many_ref(2, 0).unwrap();
+    assert_eq!(gate.num_permits(), 2);
+    let mut future = Box::pin(semaphore.acquire_many_ref(2, 10));
+    assert!(poll_once(future.as_mut()).is_pending());
+
+    drop(gate);
+    assert_eq!(semaphore.available_permits(), 0);
+    drop(future);
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[cfg(feature = "std")]
+fn wait_for_queue_blocking(semaphore: &PrioritySemaphore, expected: usize) {
+    let deadline = std::time::Instant::now() + Duration::from_secs(5);
+    while semaphore.queued() != expected {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "queue did not reach {expected}; actual={}",
+            semaphore.queued()
+        );
+        std::thread::yield_now();
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn blocking_waiters_share_priority_order_with_async_handoff() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let (tx, rx) = std::sync::mpsc::channel();
+    let mut threads = Vec::new();
+    for (index, priority) in [1, 10].into_iter().enumerate() {
+        let thread_semaphore = semaphore.clone();
+        let tx = tx.clone();
+        threads.push(std::thread::spawn(move || {
+            let _permit = thread_semaphore.acquire_blocking(priority).unwrap();
+            tx.send(priority).unwrap();
+        }));
+        wait_for_queue_blocking(&semaphore, index + 1);
+    }
+    drop(tx);
+    assert_eq!(
+        semaphore.try_acquire(i32::MAX).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    drop(gate);
+
+    assert_eq!(rx.iter().collect::<Vec<_>>(), [10, 1]);
+    for thread in threads {
+        thread.join().unwrap();
+    }
+    assert_eq!(semaphore.available_permits(), 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn blocking_timeout_reports_timeout_and_leaves_no_waiter_behind() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let gate = semaphore.try_acquire(0).unwrap();
+    assert_eq!(
+        semaphore
+            .acquire_blocking_timeout(0, Duration::from_millis(20))
+            .unwrap_err(),
+        AcquireError::Timeout
+    );
+    assert_eq!(semaphore.queued(), 0);
+
+    drop(gate);
+    let permit = semaphore
+        .acquire_blocking_timeout(0, Duration::from_secs(5))
+        .unwrap();
+    semaphore.close();
+    assert_eq!(
+        semaphore.acquire_blocking(0).unwrap_err(),
+        AcquireError::Closed
+    );
+    drop(permit);
+    assert_eq!(semaphore.available_permits(), 1);
+}
+
+/// A user-implemented timer, as a runtime without a bundled adapter would use.
+struct TestTimer;
+
+impl Timer for TestTimer {
+    type Instant = tokio::time::Instant;
+    type Sleep = Pin<Box<tokio::time::Sleep>>;
+
+    fn sleep(&self, duration: Duration) -> Self::Sleep {
+        Box::pin(tokio::time::sleep(duration))
+    }
+
+    fn sleep_until(&self, deadline: Self::Instant) -> Self::Sleep {
+        Box::pin(tokio::time::sleep_until(deadline))
+    }
+}
+
+/// A timer that never fires, leaving deadlines to the semaphore's own checks.
+#[cfg(feature = "std")]
+struct NoTimer;
+
+#[cfg(feature = "std")]
+impl Timer for NoTimer {
+    type Instant = Instant;
+    type Sleep = std::future::Pending<()>;
+
+    fn sleep(&self, _: Duration) -> Self::Sleep {
+        std::future::pending()
+    }
+
+    fn sleep_until(&self, _: Self::Instant) -> Self::Sleep {
+        std::future::pending()
+    }
+}
+
+#[tokio::test(start_paused = true)]
+async fn acquire_timeout_reports_timeout_and_withdraws_from_the_queue() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut low = Box::pin(semaphore.acquire(1));
+    assert!(poll_once(low.as_mut()).is_pending());
+
+    let result = semaphore
+        .acquire_timeout(&TestTimer, 10, Duration::from_millis(50))
+        .await;
+    assert_eq!(result.unwrap_err(), AcquireError::Timeout);
+    assert_eq!(semaphore.queued(), 1);
+
+    let deadline = tokio::time::Instant::now() + Duration::from_millis(50);
+    let waiter = tokio::spawn({
+        let semaphore = semaphore.clone();
+        async move {
+            semaphore
+                .acquire_until(&TestTimer, 10, deadline)
+                .await
+                .map(drop)
+        }
+    });
+    wait_for_queue(&semaphore, 2).await;
+    drop(gate);
+    assert_eq!(waiter.await.unwrap(), Ok(()));
+    drop(low.await.unwrap());
+    assert_eq!(semaphore.available_permits(), 1);
+}
+
+#[tokio::test(start_paused = true)]
+async fn a_permit_handed_off_before_the_timer_fires_wins() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut future = Box::pin(semaphore.acquire_timeout(&TestTimer, 0, Duration::from_millis(5)));
+    assert!(poll_once(future.as_mut()).is_pending());
+
+    drop(gate); // assigned directly; the future has not observed it yet
+    tokio::time::sleep(Duration::from_millis(10)).await;
+    let permit = future.await.unwrap();
+    assert_eq!(semaphore.available_permits(), 0);
+    drop(permit);
+    assert_eq!(semaphore.available_permits(), 1);
+}
+
+#[cfg(feature = "tokio")]
+#[tokio::test(start_paused = true)]
+async fn tokio_timer_adapter_times_out() {
+    let semaphore = Arc::new(PrioritySemaphore::new(0));
+    let timer = priority_semaphore::TokioTimer;
+    let result = semaphore
+        .acquire_timeout(&timer, 0, Duration::from_secs(1))
+        .await;
+    assert_eq!(result.unwrap_err(), AcquireError::Timeout);
+    assert_eq!(semaphore.queued(), 0);
+}
+
+#[cfg(feature = "async-io")]
+#[tokio::test]
+async fn async_io_timer_adapter_times_out() {
+    let semaphore = Arc::new(PrioritySemaphore::new(0));
+    let timer = priority_semaphore::AsyncIoTimer;
+    let deadline = std::time::Instant::now() + Duration::from_millis(10);
+    let result = semaphore.acquire_until(&timer, 0, deadline).await;
+    assert_eq!(result.unwrap_err(), AcquireError::Timeout);
+    assert_eq!(semaphore.queued(), 0);
+}
+
+#[tokio::test]
+async fn raising_priority_in_place_keeps_the_original_arrival_order() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut raised = Box::pin(semaphore.acquire(0));
+    let mut peer = Box::pin(semaphore.acquire(5));
+    assert!(poll_once(raised.as_mut()).is_pending());
+    assert!(poll_once(peer.as_mut()).is_pending());
+
+    // `raised` arrived first, so it now wins the tie at priority 5.
+    raised.set_priority(5);
+    drop(gate);
+    let permit = raised.await.unwrap();
+    assert!(poll_once(peer.as_mut()).is_pending());
+    drop(permit);
+    drop(peer.await.unwrap());
+}
+
+#[tokio::test]
+async fn priority_handle_reprioritises_from_another_task() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut background = Box::pin(semaphore.acquire(-10));
+    let mut other = Box::pin(semaphore.acquire(0));
+    assert!(background.priority_handle().is_none());
+    assert!(poll_once(background.as_mut()).is_pending());
+    assert!(poll_once(other.as_mut()).is_pending());
+
+    let handle = background.priority_handle().unwrap();
+    let raised = tokio::spawn(async move { handle.set_priority(100) });
+    assert!(raised.await.unwrap());
+
+    let handle = background.priority_handle().unwrap();
+    drop(gate);
+    let permit = background.await.unwrap();
+    assert!(!handle.set_priority(0));
+    assert!(poll_once(other.as_mut()).is_pending());
+    drop(permit);
+    drop(other.await.unwrap());
+}
+
+/// Queues one priority -2 waiter, then lets a new priority-0 waiter arrive
+/// before each release of a single permit, and reports the grant order.
+fn grant_order_with(aging: Option<Aging>) -> Vec<&'static str> {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    semaphore.set_aging(aging);
+    let mut held = semaphore.try_acquire(0).unwrap();
+    let mut futures = vec![("low", Box::pin(semaphore.acquire(-2)))];
+    assert!(poll_once(futures[0].1.as_mut()).is_pending());
+
+    let mut order = Vec::new();
+    for name in ["a", "b", "c", "d", ""] {
+        if !name.is_empty() {
+            let mut future = Box::pin(semaphore.acquire(0));
+            assert!(poll_once(future.as_mut()).is_pending());
+            futures.push((name, future));
+        }
+        drop(held);
+        let (index, permit) = futures
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, (_, future))| match poll_once(future.as_mut()) {
+                Poll::Ready(permit) => Some((index, permit.unwrap())),
+                Poll::Pending => None,
+            })
+            .expect("a released permit must be handed to a waiter");
+        order.push(futures.remove(index).0);
+        held = permit;
+    }
+    order
+}
+
+#[test]
+fn grant_based_aging_lifts_a_waiter_past_newer_peers() {
+    assert_eq!(grant_order_with(None), ["a", "b", "c", "d", "low"]);
+    // After two grants the waiter ties with new arrivals and wins by age.
+    assert_eq!(
+        grant_order_with(Some(Aging::per_grants(1, 5))),
+        ["a", "b", "low", "c", "d"]
+    );
+    assert_eq!(
+        grant_order_with(Some(Aging::per_grants(2, 5))),
+        ["a", "b", "c", "d", "low"]
+    );
+    // A cap of one keeps the waiter below every priority-0 arrival.
+    assert_eq!(
+        grant_order_with(Some(Aging::per_grants(1, 1))),
+        ["a", "b", "c", "d", "low"]
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn time_based_aging_reorders_waiters_that_are_already_queued() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut old = Box::pin(semaphore.acquire(-1));
+    assert!(poll_once(old.as_mut()).is_pending());
+    std::thread::sleep(Duration::from_millis(30));
+    let mut new = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(new.as_mut()).is_pending());
+
+    semaphore.set_aging(Some(Aging::per_duration(Duration::from_millis(10), 100)));
+    assert_eq!(semaphore.aging().unwrap().cap(), 100);
+    drop(gate);
+    assert!(poll_once(old.as_mut()).is_ready());
+    assert!(poll_once(new.as_mut()).is_ready());
+}
+
+#[tokio::test]
+async fn added_permits_go_straight_to_queued_waiters_by_priority() {
+    let semaphore = Arc::new(PrioritySemaphore::new(0));
+    let mut low = Box::pin(semaphore.acquire(1));
+    let mut high = Box::pin(semaphore.acquire(10));
+    assert!(poll_once(low.as_mut()).is_pending());
+    assert!(poll_once(high.as_mut()).is_pending());
+
+    semaphore.add_permits(1);
+    assert_eq!(semaphore.max_permits(), 1);
+    assert_eq!(semaphore.available_permits(), 0);
+    let high = high.await.unwrap();
+    assert!(poll_once(low.as_mut()).is_pending());
+
+    semaphore.add_permits(1);
+    let low = low.await.unwrap();
+    drop((high, low));
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[tokio::test]
+async fn forgotten_permits_are_taken_back_as_they_are_returned() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    let first = semaphore.try_acquire(0).unwrap();
+    let second = semaphore.try_acquire(0).unwrap();
+    semaphore.forget_permits(2);
+    assert_eq!(semaphore.max_permits(), 1);
+    assert_eq!(semaphore.available_permits(), 0);
+
+    let mut waiter = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(waiter.as_mut()).is_pending());
+    drop(first); // absorbed by the pending reduction
+    assert!(poll_once(waiter.as_mut()).is_pending());
+    assert_eq!(
+        semaphore.try_acquire(0).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    drop(second);
+    drop(waiter.await.unwrap());
+    assert_eq!(semaphore.available_permits(), 1);
+
+    // Adding permits settles a pending reduction before reaching the pool.
+    let held = semaphore.try_acquire(0).unwrap();
+    semaphore.resize(0);
+    semaphore.add_permits(2);
+    assert_eq!(semaphore.max_permits(), 2);
+    assert_eq!(semaphore.available_permits(), 1);
+    drop(held);
+    assert_eq!(semaphore.available_permits(), 2);
+}
+
+#[test]
+fn resize_and_forget_adjust_live_capacity() {
+    let semaphore = Arc::new(PrioritySemaphore::new(2));
+    semaphore.resize(5);
+    assert_eq!(semaphore.available_permits(), 5);
+    semaphore.resize(3);
+    assert_eq!(semaphore.available_permits(), 3);
+
+    let permit = semaphore.try_acquire_many(2, 0).unwrap();
+    permit.forget();
+    assert_eq!(semaphore.max_permits(), 1);
+    assert_eq!(semaphore.available_permits(), 1);
+    semaphore.forget_permits(10);
+    assert_eq!(semaphore.max_permits(), 0);
+    assert_eq!(semaphore.available_permits(), 0);
+    assert!(format!("{semaphore:?}").contains("max_permits: 0"));
+}
+
+#[test]
+fn forgetting_a_permit_owed_to_a_shrink_settles_the_shrink() {
+    let semaphore = Arc::new(PrioritySemaphore::new(2));
+    let first = semaphore.try_acquire(0).unwrap();
+    let second = semaphore.try_acquire(0).unwrap();
+    semaphore.forget_permits(2);
+    assert_eq!(semaphore.max_permits(), 0);
+
+    first.forget();
+    assert_eq!(semaphore.max_permits(), 0);
+    second.forget();
+    assert_eq!(semaphore.max_permits(), 0);
+    assert_eq!(semaphore.available_permits(), 0);
+
+    // Nothing is owed any more, so new permits reach the pool.
+    semaphore.add_permits(1);
+    assert_eq!(semaphore.max_permits(), 1);
+    assert_eq!(semaphore.available_permits(), 1);
+}
+
+/// Queues one single-permit waiter per priority, in order, behind a gate and
+/// returns the queue positions in the order they are granted.
+fn grant_order_under(policy: impl SchedulingPolicy + 'static, priorities: &[i32]) -> Vec<usize> {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    semaphore.set_scheduling_policy(policy);
+    let mut held = Some(semaphore.try_acquire(0).unwrap());
+    let mut futures: Vec<_> = priorities
+        .iter()
+        .map(|&priority| Some(Box::pin(semaphore.acquire(priority))))
+        .collect();
+    for future in futures.iter_mut().flatten() {
+        assert!(poll_once(future.as_mut()).is_pending());
+    }
+
+    let mut order = Vec::new();
+    while order.len() < priorities.len() {
+        drop(held.take());
+        let (index, permit) = futures
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, slot)| {
+                let future = slot.as_mut()?;
+                match poll_once(future.as_mut()) {
+                    Poll::Ready(permit) => {
+                        *slot = None;
+                        Some((index, permit.unwrap()))
+                    }
+                    Poll::Pending => None,
+                }
+            })
+            .expect("a released permit must be handed to a waiter");
+        order.push(index);
+        held = Some(permit);
+    }
+    order
+}
+
+#[derive(Debug)]
+struct FewestPermitsFirst;
+
+impl SchedulingPolicy for FewestPermitsFirst {
+    fn outranks(&self, waiter: &WaiterInfo, other: &WaiterInfo) -> bool {
+        (other.permits(), other.arrival()) > (waiter.permits(), waiter.arrival())
+    }
+}
+
+#[test]
+fn bundled_scheduling_policies_order_grants() {
+    let priorities = [1, 5, 1, 5, 3];
+    assert_eq!(
+        grant_order_under(PriorityLifo, &priorities),
+        [3, 1, 4, 2, 0]
+    );
+    assert_eq!(
+        grant_order_under(EarliestDeadlineFirst, &priorities),
+        [0, 2, 4, 1, 3]
+    );
+    assert_eq!(
+        grant_order_under(RoundRobin::default(), &priorities),
+        [1, 4, 0, 3, 2]
+    );
+}
+
+#[tokio::test]
+async fn a_custom_scheduling_policy_reorders_queued_waiters() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    let gate = semaphore.try_acquire_many(3, 0).unwrap();
+    let mut large = Box::pin(semaphore.acquire_many(3, 100));
+    let mut small = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(large.as_mut()).is_pending());
+    assert!(poll_once(small.as_mut()).is_pending());
+
+    semaphore.set_scheduling_policy(FewestPermitsFirst);
+    drop(gate);
+    let small = small.await.unwrap();
+    assert!(poll_once(large.as_mut()).is_pending());
+    drop(small);
+    assert_eq!(large.await.unwrap().num_permits(), 3);
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Tier {
+    Free,
+    Paid,
+}
+
+#[tokio::test]
+async fn any_ordered_type_can_be_the_priority() {
+    let semaphore = Arc::new(PrioritySemaphore::<(Tier, u32)>::with_permits(1));
+    let gate = semaphore.try_acquire((Tier::Free, 0)).unwrap();
+    let mut free = Box::pin(semaphore.acquire((Tier::Free, 9)));
+    let mut paid_low = Box::pin(semaphore.acquire((Tier::Paid, 1)));
+    let mut paid_high = Box::pin(semaphore.acquire((Tier::Paid, 2)));
+    for future in [&mut free, &mut paid_low, &mut paid_high] {
+        assert!(poll_once(future.as_mut()).is_pending());
+    }
+
+    paid_low.set_priority((Tier::Paid, 3));
+    drop(gate);
+    let permit = paid_low.await.unwrap();
+    assert!(poll_once(free.as_mut()).is_pending());
+    drop(permit);
+    let permit = paid_high.await.unwrap();
+    drop(permit);
+    free.await.unwrap();
+
+    // Reverse turns the smallest deadline into the greatest priority.
+    let deadlines = Arc::new(PrioritySemaphore::<Reverse<u64>>::with_permits(1));
+    deadlines.set_scheduling_policy(RoundRobin::default());
+    let gate = deadlines.try_acquire(Reverse(0)).unwrap();
+    let mut late = Box::pin(deadlines.acquire(Reverse(20)));
+    let mut soon = Box::pin(deadlines.acquire(Reverse(10)));
+    assert!(poll_once(late.as_mut()).is_pending());
+    assert!(poll_once(soon.as_mut()).is_pending());
+    drop(gate);
+    assert!(poll_once(late.as_mut()).is_pending());
+    drop(soon.await.unwrap());
+    late.await.unwrap();
+}
+
+#[cfg(feature = "std")]
+#[tokio::test]
+async fn deadline_semaphore_serves_the_earliest_deadline_and_fails_expired_waiters() {
+    let semaphore = Arc::new(DeadlineSemaphore::with_permits(1));
+    let start = Instant::now();
+    let gate = semaphore
+        .acquire_by(&NoTimer, start + Duration::from_secs(60))
+        .await
+        .unwrap();
+    let mut late = Box::pin(semaphore.acquire_by(&NoTimer, start + Duration::from_secs(60)));
+    let mut soon = Box::pin(semaphore.acquire_by(&NoTimer, start + Duration::from_secs(30)));
+    let mut expiring = Box::pin(semaphore.acquire_by(&NoTimer, start + Duration::from_millis(20)));
+    for future in [&mut late, &mut soon, &mut expiring] {
+        assert!(poll_once(future.as_mut()).is_pending());
+    }
+
+    std::thread::sleep(Duration::from_millis(30));
+    drop(gate);
+    assert_eq!(expiring.await.unwrap_err(), AcquireError::DeadlineExpired);
+    let permit = soon.await.unwrap();
+    assert!(poll_once(late.as_mut()).is_pending());
+    drop(permit);
+    late.await.unwrap();
+
+    // A deadline that has already passed fails even with permits available.
+    assert_eq!(
+        semaphore.acquire_by(&NoTimer, start).await.unwrap_err(),
+        AcquireError::DeadlineExpired
+    );
+    assert_eq!(semaphore.available_permits(), 1);
+    assert_eq!(semaphore.deadlines_met(), 3);
+    assert_eq!(semaphore.deadlines_missed(), 2);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn expired_waiters_fail_without_a_release() {
+    let semaphore = Arc::new(DeadlineSemaphore::with_permits(1));
+    let start = Instant::now();
+    let gate = semaphore.try_acquire(Reverse(start)).unwrap();
+    let mut later = Box::pin(semaphore.acquire_by(&NoTimer, start + Duration::from_secs(60)));
+    let mut polled = Box::pin(semaphore.acquire_by(&NoTimer, start + Duration::from_millis(20)));
+    assert!(poll_once(later.as_mut()).is_pending());
+    assert!(poll_once(polled.as_mut()).is_pending());
+
+    // Polling after the deadline withdraws the waiter.
+    std::thread::sleep(Duration::from_millis(30));
+    assert!(matches!(
+        poll_once(polled.as_mut()),
+        Poll::Ready(Err(AcquireError::DeadlineExpired))
+    ));
+    assert_eq!(semaphore.queued(), 1);
+
+    // An arrival sweeps an expired head out of the queue.
+    let mut swept =
+        Box::pin(semaphore.acquire_by(&NoTimer, Instant::now() + Duration::from_millis(20)));
+    assert!(poll_once(swept.as_mut()).is_pending());
+    std::thread::sleep(Duration::from_millis(30));
+    let mut arrival = Box::pin(semaphore.acquire_by(&NoTimer, start + Duration::from_secs(90)));
+    assert!(poll_once(arrival.as_mut()).is_pending());
+    assert_eq!(semaphore.queued(), 2);
+    assert_eq!(semaphore.deadlines_missed(), 2);
+    assert!(matches!(
+        poll_once(swept.as_mut()),
+        Poll::Ready(Err(AcquireError::DeadlineExpired))
+    ));
+
+    drop(gate);
+    assert!(poll_once(later.as_mut()).is_ready());
+    assert_eq!(semaphore.deadlines_missed(), 2);
+}
+
+#[cfg(feature = "std")]
+#[tokio::test(start_paused = true)]
+async fn acquire_by_wakes_and_fails_at_its_deadline() {
+    let semaphore = Arc::new(DeadlineSemaphore::with_permits(1));
+    let gate = semaphore.try_acquire(Reverse(Instant::now())).unwrap();
+    let mut later =
+        Box::pin(semaphore.acquire_by(&TestTimer, Instant::now() + Duration::from_secs(90)));
+    assert!(poll_once(later.as_mut()).is_pending());
+
+    // Nothing else touches the semaphore; the timer alone ends the wait.
+    let result = semaphore
+        .acquire_by(&TestTimer, Instant::now() + Duration::from_secs(60))
+        .await;
+    assert_eq!(result.unwrap_err(), AcquireError::DeadlineExpired);
+    assert_eq!(semaphore.queued(), 1);
+    assert_eq!(semaphore.deadlines_missed(), 1);
+
+    drop(gate);
+    assert!(poll_once(later.as_mut()).is_ready());
+    assert_eq!(semaphore.deadlines_met(), 1);
+}
+
+#[tokio::test]
+async fn a_full_queue_turns_away_or_evicts_the_lowest_waiter() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    semaphore.set_max_queued(Some(2));
+    assert_eq!(semaphore.max_queued(), Some(2));
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut low = Box::pin(semaphore.acquire(1));
+    let mut middle = Box::pin(semaphore.acquire(5));
+    assert!(poll_once(low.as_mut()).is_pending());
+    assert!(poll_once(middle.as_mut()).is_pending());
+
+    // Equal rank loses to the waiter that arrived first.
+    assert_eq!(
+        semaphore.acquire(1).await.unwrap_err(),
+        AcquireError::QueueFull
+    );
+    let mut high = Box::pin(semaphore.acquire(9));
+    assert!(poll_once(high.as_mut()).is_pending());
+    assert_eq!(low.await.unwrap_err(), AcquireError::Evicted);
+    assert_eq!(semaphore.queued(), 2);
+
+    semaphore.set_max_queued(Some(1));
+    assert_eq!(middle.await.unwrap_err(), AcquireError::Evicted);
+    drop(gate);
+    high.await.unwrap();
+
+    semaphore.set_max_queued(None);
+    let gate = semaphore.try_acquire(0).unwrap();
+    let mut queued: Vec<_> = (0..4).map(|_| Box::pin(semaphore.acquire(0))).collect();
+    for future in &mut queued {
+        assert!(poll_once(future.as_mut()).is_pending());
+    }
+    assert_eq!(semaphore.queued(), 4);
+    drop(gate);
+}
+
+#[tokio::test]
+async fn admission_rules_reject_low_priorities_while_busy() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    semaphore.set_admission_rules([
+        AdmissionRule::new(0, Busy::QueueDeeperThan(1)),
+        AdmissionRule::new(10, Busy::NoPermits),
+    ]);
+    // Rules never apply while the acquisition can be granted at once.
+    let gate = semaphore.try_acquire(-5).unwrap();
+
+    assert_eq!(
+        semaphore.try_acquire(9).unwrap_err(),
+        TryAcquireError::Rejected(Busy::NoPermits)
+    );
+    assert_eq!(
+        semaphore.try_acquire(10).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    assert_eq!(
+        semaphore.acquire(9).await.unwrap_err(),
+        AcquireError::Rejected(Busy::NoPermits)
+    );
+
+    semaphore.set_admission_rules([AdmissionRule::new(0, Busy::QueueDeeperThan(1))]);
+    let mut first = Box::pin(semaphore.acquire(-1));
+    let mut second = Box::pin(semaphore.acquire(-1));
+    assert!(poll_once(first.as_mut()).is_pending());
+    assert!(poll_once(second.as_mut()).is_pending());
+    let error = semaphore.acquire(-1).await.unwrap_err();
+    assert_eq!(error, AcquireError::Rejected(Busy::QueueDeeperThan(1)));
+    assert_eq!(
+        error.to_string(),
+        "rejected: more than 1 acquisitions queued"
+    );
+    let mut admitted = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(admitted.as_mut()).is_pending());
+    assert_eq!(semaphore.queued(), 3);
+
+    semaphore.set_admission_rules([]);
+    assert!(semaphore.admission_rules().is_empty());
+    let mut late = Box::pin(semaphore.acquire(-1));
+    assert!(poll_once(late.as_mut()).is_pending());
+    drop(gate);
+}
+
+#[tokio::test]
+async fn reserved_permits_are_left_for_their_band() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    semaphore.set_reservations([Reservation::new(100, 1)]);
+    let bulk = semaphore.try_acquire_many(2, 0).unwrap();
+    assert_eq!(
+        semaphore.try_acquire(99).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    let health = semaphore.try_acquire(100).unwrap();
+
+    // A released permit is not handed to a lower band if that would eat into
+    // the reservation.
+    let mut waiting_bulk = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(waiting_bulk.as_mut()).is_pending());
+    drop(health);
+    assert!(poll_once(waiting_bulk.as_mut()).is_pending());
+    drop(bulk);
+    let waiting_bulk = waiting_bulk.await.unwrap();
+    assert_eq!(semaphore.available_permits(), 2);
+
+    // A head held back by the reservation does not block the band.
+    semaphore.set_scheduling_policy(EarliestDeadlineFirst);
+    let rest = semaphore.try_acquire(0).unwrap();
+    let mut blocked_head = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(blocked_head.as_mut()).is_pending());
+    let control = semaphore.acquire(100).await.unwrap();
+    drop(waiting_bulk);
+    assert!(poll_once(blocked_head.as_mut()).is_pending());
+
+    semaphore.set_reservations([]);
+    blocked_head.await.unwrap();
+    drop((rest, control));
+}
+
+#[test]
+fn reserved_band_try_acquire_passes_waiters_held_back_by_the_reservation() {
+    let semaphore = Arc::new(PrioritySemaphore::new(2));
+    semaphore.set_reservations([Reservation::new(100, 1)]);
+    let bulk = semaphore.try_acquire(0).unwrap();
+    let mut waiting_bulk = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(waiting_bulk.as_mut()).is_pending());
+
+    // The queued waiter may not take the reserved permit, so it does not
+    // stand in the way of the band the permit is reserved for.
+    assert_eq!(
+        semaphore.try_acquire(50).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    let control = semaphore.try_acquire(100).unwrap();
+    assert_eq!(semaphore.available_permits(), 0);
+
+    // A waiter that is only short of permits still keeps its place.
+    drop(control);
+    let mut large = Box::pin(semaphore.acquire_many(2, 200));
+    assert!(poll_once(large.as_mut()).is_pending());
+    assert_eq!(
+        semaphore.try_acquire(100).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    drop(bulk);
+    assert!(poll_once(large.as_mut()).is_ready());
+    assert!(poll_once(waiting_bulk.as_mut()).is_ready());
+}
+
+#[test]
+fn try_acquire_does_not_take_permits_pooled_for_a_lower_waiter() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    semaphore.set_reservations([Reservation::new(5, 1)]);
+    let first = semaphore.try_acquire_many(2, 5).unwrap();
+    let second = semaphore.try_acquire(5).unwrap();
+    let mut head = Box::pin(semaphore.acquire_many(3, 10));
+    assert!(poll_once(head.as_mut()).is_pending());
+
+    // The returned permits are pooled for the head, which is only short of
+    // permits, so a higher priority may not take them.
+    drop(first);
+    assert_eq!(
+        semaphore.try_acquire(20).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    let mut higher = Box::pin(semaphore.acquire(20));
+    assert!(poll_once(higher.as_mut()).is_pending());
+
+    // The higher waiter is served from the next returned permit.
+    drop(second);
+    let higher = match poll_once(higher.as_mut()) {
+        Poll::Ready(permit) => permit.unwrap(),
+        Poll::Pending => panic!("a returned permit should go to the higher waiter"),
+    };
+    assert!(poll_once(head.as_mut()).is_pending());
+    drop(higher);
+    assert!(poll_once(head.as_mut()).is_ready());
+}
+
+#[tokio::test]
+async fn capped_bands_hold_at_most_their_permits() {
+    let semaphore = Arc::new(PrioritySemaphore::new(4));
+    semaphore.set_caps([Cap::new(100, 1), Cap::new(0, 2)]);
+    assert_eq!(semaphore.caps(), [Cap::new(0, 2), Cap::new(100, 1)]);
+    let batch = semaphore.try_acquire_many(2, -1).unwrap();
+    assert_eq!(
+        semaphore.try_acquire(-5).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    drop(batch);
+
+    let batch = semaphore.try_acquire(-1).unwrap();
+    let normal = semaphore.try_acquire(50).unwrap();
+    let control = semaphore.try_acquire(100).unwrap();
+    let extra = semaphore.try_acquire(100).unwrap();
+
+    // A waiter whose band is full is passed over for the next one.
+    let mut capped = Box::pin(semaphore.acquire(60));
+    assert!(poll_once(capped.as_mut()).is_pending());
+    let mut low = Box::pin(semaphore.acquire(-5));
+    assert!(poll_once(low.as_mut()).is_pending());
+    drop(control);
+    let low = low.await.unwrap();
+    assert!(poll_once(capped.as_mut()).is_pending());
+    drop(normal);
+    let capped = capped.await.unwrap();
+
+    // Forgetting a permit makes room in its band as well.
+    let mut lower = Box::pin(semaphore.acquire(-9));
+    assert!(poll_once(lower.as_mut()).is_pending());
+    drop(extra);
+    assert!(poll_once(lower.as_mut()).is_pending());
+    low.forget();
+    let lower = lower.await.unwrap();
+    assert_eq!(semaphore.max_permits(), 3);
+    drop((batch, capped, lower));
+    assert_eq!(semaphore.available_permits(), 3);
+}
+
+#[test]
+fn waiters_in_a_full_band_do_not_block_try_acquire() {
+    let semaphore = Arc::new(PrioritySemaphore::new(4));
+    semaphore.set_caps([Cap::new(0, 1)]);
+    let batch = semaphore.try_acquire(-1).unwrap();
+    let mut waiting_batch = Box::pin(semaphore.acquire(-1));
+    assert!(poll_once(waiting_batch.as_mut()).is_pending());
+
+    let normal = semaphore.try_acquire(10).unwrap();
+    assert_eq!(semaphore.available_permits(), 2);
+    assert_eq!(
+        semaphore.try_acquire(-5).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+
+    drop(batch);
+    assert!(poll_once(waiting_batch.as_mut()).is_ready());
+    drop(normal);
+    assert_eq!(semaphore.available_permits(), 4);
+}
+
+#[test]
+fn caps_do_not_let_try_acquire_take_permits_pooled_for_a_lower_waiter() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    semaphore.set_caps([Cap::new(-100, 5)]);
+    let first = semaphore.try_acquire_many(2, 0).unwrap();
+    let second = semaphore.try_acquire(0).unwrap();
+    let mut head = Box::pin(semaphore.acquire_many(3, 10));
+    assert!(poll_once(head.as_mut()).is_pending());
+
+    drop(first);
+    assert_eq!(
+        semaphore.try_acquire(20).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    let mut higher = Box::pin(semaphore.acquire(20));
+    assert!(poll_once(higher.as_mut()).is_pending());
+    drop(second);
+    drop(higher);
+    assert!(poll_once(head.as_mut()).is_ready());
+}
+
+#[test]
+fn tenants_share_permits_in_proportion_to_their_weights() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let mut held = Some(semaphore.try_acquire(0).unwrap());
+    // The light tenant's much higher priorities only order its own requests.
+    let mut futures: Vec<_> = (0..8)
+        .flat_map(|priority| [("heavy", 1, 3, priority), ("light", 2, 1, 100 + priority)])
+        .map(|(name, tenant, weight, priority)| {
+            let future = Box::pin(semaphore.acquire_for(tenant, weight, priority));
+            (name, priority, Some(future))
+        })
+        .collect();
+    for (_, _, future) in &mut futures {
+        assert!(poll_once(future.as_mut().unwrap().as_mut()).is_pending());
+    }
+
+    let mut order = Vec::new();
+    for _ in 0..8 {
+        drop(held.take());
+        let (name, priority, permit) = futures
+            .iter_mut()
+            .find_map(|(name, priority, slot)| {
+                let future = slot.as_mut()?;
+                match poll_once(future.as_mut()) {
+                    Poll::Ready(permit) => {
+                        *slot = None;
+                        Some((*name, *priority, permit.unwrap()))
+                    }
+                    Poll::Pending => None,
+                }
+            })
+            .expect("a released permit must be handed to a waiter");
+        order.push((name, priority));
+        held = Some(permit);
+    }
+    assert_eq!(
+        order,
+        [
+            ("heavy", 7),
+            ("light", 107),
+            ("heavy", 6),
+            ("heavy", 5),
+            ("heavy", 4),
+            ("light", 106),
+            ("heavy", 3),
+            ("heavy", 2),
+        ]
+    );
+
+    drop(futures);
+    assert_eq!(semaphore.queued(), 0);
+    drop(held);
+    assert_eq!(semaphore.available_permits(), 1);
+}
+
+#[tokio::test]
+async fn child_permits_also_hold_their_parents_permits() {
+    let cluster = Arc::new(PrioritySemaphore::new(2));
+    let service = cluster.child(4);
+    let small = service.child(1);
+    let large = service.child(3);
+    assert!(Arc::ptr_eq(large.parent().unwrap(), &service));
+    assert!(cluster.parent().is_none());
+
+    let first = small.acquire(0).await.unwrap();
+    assert_eq!(
+        small.try_acquire(0).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    let second = large.try_acquire(0).unwrap();
+    assert_eq!(service.available_permits(), 2);
+    assert_eq!(cluster.available_permits(), 0);
+
+    // Failing at the cluster gives the lower levels' permits back.
+    assert_eq!(
+        large.try_acquire(5).unwrap_err(),
+        TryAcquireError::NoPermits
+    );
+    assert_eq!(large.available_permits(), 2);
+    assert_eq!(service.available_permits(), 2);
+
+    // Waiters climb to the cluster and keep their priority there.
+    let mut low = Box::pin(large.acquire(1));
+    assert!(poll_once(low.as_mut()).is_pending());
+    let mut high = Box::pin(service.acquire(9));
+    assert!(poll_once(high.as_mut()).is_pending());
+    assert_eq!(cluster.queued(), 2);
+    drop(first);
+    let high = high.await.unwrap();
+    assert!(poll_once(low.as_mut()).is_pending());
+
+    // Cancelling at the cluster returns the permits already taken below.
+    drop(low);
+    assert_eq!(cluster.queued(), 0);
+    assert_eq!(large.available_permits(), 2);
+    drop((second, high));
+    assert_eq!(large.available_permits(), 3);
+    assert_eq!(service.available_permits(), 4);
+    assert_eq!(cluster.available_permits(), 2);
+
+    small.try_acquire(0).unwrap().forget();
+    assert_eq!(small.max_permits(), 0);
+    assert_eq!(service.max_permits(), 3);
+    assert_eq!(cluster.max_permits(), 1);
+}
+
+#[tokio::test]
+async fn acquire_all_takes_every_semaphore_in_one_global_order() {
+    let a = PrioritySemaphore::new(1);
+    let b = PrioritySemaphore::new(2);
+
+    let set = acquire_all(&[&b, &a, &b], 0).await.unwrap();
+    assert_eq!(set.num_permits(), 3);
+    assert_eq!((a.available_permits(), b.available_permits()), (0, 0));
+
+    // Opposite argument orders queue on the same semaphore first instead of
+    // each holding what the other needs.
+    let mut forward = Box::pin(acquire_all(&[&a, &b], 5));
+    let mut backward = Box::pin(acquire_all(&[&b, &a], 1));
+    assert!(poll_once(forward.as_mut()).is_pending());
+    assert!(poll_once(backward.as_mut()).is_pending());
+    drop(set);
+    let forward = forward.await.unwrap();
+    assert!(poll_once(backward.as_mut()).is_pending());
+    assert_eq!(forward.into_permits().len(), 2);
+    let backward = backward.await.unwrap();
+    assert_eq!(backward.num_permits(), 2);
+    drop(backward);
+
+    // A failure part-way through returns the permits already taken.
+    let held = a.try_acquire_ref(0).unwrap();
+    let held_b = b.try_acquire_many_ref(2, 0).unwrap();
+    let mut partial = Box::pin(acquire_all(&[&a, &b], 0));
+    assert!(poll_once(partial.as_mut()).is_pending());
+    drop(held_b);
+    assert!(poll_once(partial.as_mut()).is_pending());
+    a.close();
+    b.close();
+    assert_eq!(partial.await.unwrap_err(), AcquireError::Closed);
+    drop(held);
+    assert_eq!((a.available_permits(), b.available_permits()), (1, 2));
+}
+
+#[tokio::test]
+async fn acquire_all_folds_ancestors_and_takes_children_first() {
+    let parent = Arc::new(PrioritySemaphore::new(1));
+    let child = parent.child(1);
+    let other = Arc::new(PrioritySemaphore::new(1));
+
+    // The child's permit already holds one of the parent's.
+    let set = acquire_all(&[&parent, &child], 0).await.unwrap();
+    assert_eq!(set.num_permits(), 2);
+    assert_eq!(
+        (parent.available_permits(), child.available_permits()),
+        (0, 0)
+    );
+    drop(set);
+    assert_eq!(parent.available_permits(), 1);
+
+    // Listing the parent once more than its children need asks for more.
+    let mut more = Box::pin(acquire_all(&[&parent, &parent, &child], 0));
+    assert!(poll_once(more.as_mut()).is_pending());
+    parent.add_permits(1);
+    let set = more.await.unwrap();
+    assert_eq!(set.num_permits(), 3);
+    let [child_guard, parent_guard] = <[_; 2]>::try_from(set.into_permits()).unwrap();
+    assert_eq!(
+        (child_guard.num_permits(), parent_guard.num_permits()),
+        (1, 1)
+    );
+
+    // The child's guard keeps its parent permit on its own, and forgetting
+    // it forgets that permit as well.
+    drop(parent_guard);
+    assert_eq!(parent.available_permits(), 1);
+    child_guard.forget();
+    assert_eq!(
+        (parent.available_permits(), child.available_permits()),
+        (1, 0)
+    );
+    child.add_permits(1);
+    parent.add_permits(1);
+
+    // Whatever else is listed, the child and then its parent come first, in
+    // the same order a plain child acquisition climbs in.
+    let held = child.try_acquire(0).unwrap();
+    let mut with_other = Box::pin(acquire_all(&[&other, &child], 0));
+    assert!(poll_once(with_other.as_mut()).is_pending());
+    assert_eq!(other.available_permits(), 1);
+    drop(held);
+    let set = with_other.await.unwrap();
+    assert_eq!(set.into_permits().len(), 2);
+    assert_eq!(
+        (parent.available_permits(), other.available_permits()),
+        (2, 1)
+    );
+}
+
+#[test]
+#[should_panic(expected = "acquire future polled after completion")]
+fn acquire_all_panics_when_polled_after_completion() {
+    let semaphore = PrioritySemaphore::new(1);
+    let mut all = Box::pin(acquire_all(&[&semaphore], 0));
+    assert!(poll_once(all.as_mut()).is_ready());
+    let _ = poll_once(all.as_mut());
+}
+
+#[tokio::test]
+async fn acquire_any_takes_the_first_grant_and_passes_the_rest_on() {
+    let replicas: Vec<_> = (0..3)
+        .map(|_| Arc::new(PrioritySemaphore::new(1)))
+        .collect();
+    let held: Vec<_> = replicas.iter().map(|r| r.try_acquire(0).unwrap()).collect();
+
+    let mut any = Box::pin(acquire_any(&replicas, 5));
+    assert!(poll_once(any.as_mut()).is_pending());
+    assert!(replicas.iter().all(|replica| replica.queued() == 1));
+    let mut behind = Box::pin(replicas[2].acquire(0));
+    assert!(poll_once(behind.as_mut()).is_pending());
+
+    // Two replicas hand over before the future runs again: the first wins and
+    // the second permit moves on to that replica's next waiter.
+    let [first, second, third] = <[_; 3]>::try_from(held).unwrap();
+    drop((third, first));
+    let (replica, permit) = any.await.unwrap();
+    assert_eq!(replica, 0);
+    assert!(replicas.iter().all(|replica| replica.queued() == 0));
+    let behind = behind.await.unwrap();
+    drop((permit, behind));
+    assert_eq!(replicas[0].available_permits(), 1);
+    assert_eq!(replicas[2].available_permits(), 1);
+    drop(second);
+
+    // Closed replicas drop out; the call fails only when all of them have.
+    replicas[0].close();
+    replicas[1].close();
+    let (replica, _permit) = acquire_any(&replicas, 0).await.unwrap();
+    assert_eq!(replica, 2);
+    assert_eq!(
+        acquire_any(&replicas[..2], 0).await.unwrap_err(),
+        AcquireError::Closed
+    );
+}
+
+#[test]
+#[should_panic(expected = "acquire future polled after completion")]
+fn acquire_any_panics_when_polled_after_completion() {
+    let replicas = [Arc::new(PrioritySemaphore::new(2))];
+    let mut any = Box::pin(acquire_any(&replicas, 0));
+    assert!(poll_once(any.as_mut()).is_ready());
+    let _ = poll_once(any.as_mut());
+}
+
+#[tokio::test]
+async fn higher_priority_waiters_revoke_preemptible_permits() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    let batch = semaphore.acquire_preemptible(0).await.unwrap();
+    let report = semaphore.acquire_preemptible(1).await.unwrap();
+    let pinned = semaphore.acquire(0).await.unwrap();
+    assert!(!batch.is_revoked());
+
+    // Only a strictly higher priority asks for permits back.
+    let mut peer = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(peer.as_mut()).is_pending());
+    assert!(!batch.is_revoked() && !report.is_revoked());
+
+    let mut urgent = Box::pin(semaphore.acquire(1000));
+    assert!(poll_once(urgent.as_mut()).is_pending());
+    let mut revoked = Box::pin(batch.revoked());
+    assert_eq!(poll_once(revoked.as_mut()), Poll::Ready(()));
+    assert!(batch.is_revoked() && !report.is_revoked());
+    assert!(poll_once(Box::pin(pinned.revoked()).as_mut()).is_pending());
+    assert!(!pinned.is_revoked());
+
+    drop(batch);
+    let urgent = urgent.await.unwrap();
+
+    // A larger request revokes as many holders as it needs.
+    let mut bulk = Box::pin(semaphore.acquire_many(2, 2000));
+    assert!(poll_once(bulk.as_mut()).is_pending());
+    assert!(report.is_revoked());
+    drop((report, pinned, urgent, peer));
+    assert_eq!(bulk.await.unwrap().num_permits(), 2);
+}
+
+#[tokio::test]
+async fn raised_waiters_revoke_holders_beyond_the_preemption_margin() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    semaphore.set_preemption_margin(10);
+    assert_eq!(semaphore.preemption_margin(), 10);
+    let batch = semaphore.acquire_preemptible(0).await.unwrap();
+
+    // Within the margin, a higher priority is not enough.
+    let mut close = Box::pin(semaphore.acquire(10));
+    assert!(poll_once(close.as_mut()).is_pending());
+    assert!(!batch.is_revoked());
+
+    // Raising a queued waiter past the margin revokes the holder.
+    close.set_priority(11);
+    assert!(batch.is_revoked());
+    drop(batch);
+    assert_eq!(close.await.unwrap().num_permits(), 1);
+}
+
+#[tokio::test]
+async fn raising_a_waiter_again_only_revokes_what_it_still_needs() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    let holders = [
+        semaphore.acquire_preemptible(0).await.unwrap(),
+        semaphore.acquire_preemptible(0).await.unwrap(),
+        semaphore.acquire_preemptible(0).await.unwrap(),
+    ];
+    let revoked = || holders.iter().filter(|holder| holder.is_revoked()).count();
+
+    let mut waiter = Box::pin(semaphore.acquire(5));
+    assert!(poll_once(waiter.as_mut()).is_pending());
+    assert_eq!(revoked(), 1);
+
+    // The holder revoked for the waiter still covers it.
+    waiter.set_priority(6);
+    waiter.set_priority(7);
+    assert_eq!(revoked(), 1);
+
+    // Lowering the priority never revokes anything.
+    let mut other = Box::pin(semaphore.acquire(4));
+    assert!(poll_once(other.as_mut()).is_pending());
+    assert_eq!(revoked(), 2);
+    other.set_priority(3);
+    other.set_priority(2);
+    assert_eq!(revoked(), 2);
+}
+
+#[tokio::test]
+async fn reopening_a_closed_semaphore_keeps_its_capacity() {
+    let semaphore = Arc::new(PrioritySemaphore::new(3));
+    let held = semaphore.acquire_many(2, 0).await.unwrap();
+    let returned = semaphore.acquire(0).await.unwrap();
+    let mut queued = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(queued.as_mut()).is_pending());
+
+    semaphore.close();
+    drop(returned);
+    assert_eq!(queued.await.unwrap_err(), AcquireError::Closed);
+    assert_eq!(
+        semaphore.try_acquire(0).unwrap_err(),
+        TryAcquireError::Closed
+    );
+
+    semaphore.reopen();
+    semaphore.reopen();
+    assert!(!semaphore.is_closed());
+    assert_eq!(semaphore.available_permits(), 1);
+    let permit = semaphore.try_acquire(0).unwrap();
+    let mut waiting = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(waiting.as_mut()).is_pending());
+
+    // Permits held across the close are handed off like any other.
+    drop(held);
+    assert_eq!(waiting.await.unwrap().num_permits(), 1);
+    drop(permit);
+    assert_eq!(semaphore.available_permits(), 3);
+}
+
+#[derive(Debug, PartialEq)]
+struct Reload(u32);
+
+impl std::fmt::Display for Reload {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "config reload {}", self.0)
+    }
+}
+
+impl std::error::Error for Reload {}
+
+#[tokio::test]
+async fn close_with_delivers_its_reason_to_every_waiter() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let _held = semaphore.acquire(0).await.unwrap();
+    let mut queued = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(queued.as_mut()).is_pending());
+
+    semaphore.close_with(Reload(7));
+    semaphore.close_with(Reload(8));
+    let AcquireError::ClosedWith(reason) = queued.await.unwrap_err() else {
+        panic!("the close reason was lost");
+    };
+    assert_eq!(reason.downcast_ref(), Some(&Reload(7)));
+    assert_eq!(
+        semaphore.acquire(0).await.unwrap_err().to_string(),
+        "semaphore closed: config reload 7"
+    );
+    assert_eq!(
+        semaphore.try_acquire(0).unwrap_err(),
+        TryAcquireError::ClosedWith(reason.clone())
+    );
+    #[cfg(feature = "std")]
+    {
+        let error = semaphore.acquire_ref(0).await.unwrap_err();
+        assert!(std::error::Error::source(&error).is_some());
+    }
+
+    // A waiter keeps the reason it was failed with, even if the semaphore
+    // is reopened or closed again before the waiter is polled.
+    semaphore.reopen();
+    let mut queued = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(queued.as_mut()).is_pending());
+    semaphore.close_with(Reload(9));
+    semaphore.reopen();
+    semaphore.close();
+    let AcquireError::ClosedWith(reason) = queued.await.unwrap_err() else {
+        panic!("the close reason was lost");
+    };
+    assert_eq!(reason.downcast_ref(), Some(&Reload(9)));
+    assert_eq!(
+        semaphore.acquire(0).await.unwrap_err(),
+        AcquireError::Closed
+    );
+}
+
+#[tokio::test]
+async fn closed_and_idle_waits_for_every_outstanding_permit() {
+    let semaphore = Arc::new(PrioritySemaphore::new(2));
+    let first = semaphore.acquire(0).await.unwrap();
+    let second = semaphore.acquire(0).await.unwrap();
+    let mut handed_off = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(handed_off.as_mut()).is_pending());
+    let mut idle = Box::pin(semaphore.closed_and_idle());
+    assert!(poll_once(idle.as_mut()).is_pending());
+
+    // The handoff is not polled again before the close, so its permit is
+    // still out.
+    drop(first);
+    semaphore.close();
+    assert!(poll_once(idle.as_mut()).is_pending());
+    second.forget();
+    assert!(poll_once(idle.as_mut()).is_pending());
+    drop(handed_off);
+    idle.await;
+    assert_eq!(semaphore.available_permits(), semaphore.max_permits());
+
+    // Once idle, it stays idle.
+    semaphore.closed_and_idle().await;
+}
+
+/// Waker that counts how often it was woken.
+struct CountingWaker(AtomicUsize);
+
+impl std::task::Wake for CountingWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+#[test]
+fn dropped_closed_and_idle_futures_are_not_woken() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let permit = semaphore.try_acquire(0).unwrap();
+    let dropped = Arc::new(CountingWaker(0.into()));
+    let kept = Arc::new(CountingWaker(0.into()));
+
+    // Both are polled before the close and one goes away again.
+    let mut idle = Box::pin(semaphore.closed_and_idle());
+    let waker = Waker::from(dropped.clone());
+    assert!(
+        idle.as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending()
+    );
+    drop(idle);
+    let mut idle = Box::pin(semaphore.closed_and_idle());
+    let waker = Waker::from(kept.clone());
+    assert!(
+        idle.as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_pending()
+    );
+
+    semaphore.close();
+    drop(permit);
+    assert_eq!(dropped.0.load(Ordering::SeqCst), 0);
+    assert_eq!(kept.0.load(Ordering::SeqCst), 1);
+    assert!(
+        idle.as_mut()
+            .poll(&mut Context::from_waker(&waker))
+            .is_ready()
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn closed_and_idle_blocking_returns_once_the_last_permit_is_back() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let permit = semaphore.try_acquire(0).unwrap();
+    semaphore.close();
+    let waiter = {
+        let semaphore = semaphore.clone();
+        std::thread::spawn(move || semaphore.closed_and_idle_blocking())
+    };
+    std::thread::sleep(Duration::from_millis(10));
+    assert!(!waiter.is_finished());
+    drop(permit);
+    waiter.join().unwrap();
+}
+
+#[tokio::test]
+async fn paused_semaphores_keep_waiters_queued_until_resumed() {
+    let semaphore = Arc::new(PrioritySemaphore::new(1));
+    let held = semaphore.acquire(0).await.unwrap();
+    semaphore.pause();
+    assert!(semaphore.is_paused());
+
+    let mut low = Box::pin(semaphore.acquire(1));
+    let mut high = Box::pin(semaphore.acquire(5));
+    assert!(poll_once(low.as_mut()).is_pending());
+    assert!(poll_once(high.as_mut()).is_pending());
+
+    // The returned permit waits in the pool instead of being handed off.
+    drop(held);
+    assert_eq!(semaphore.available_permits(), 1);
+    assert!(poll_once(high.as_mut()).is_pending());
+    assert_eq!(
+        semaphore.try_acquire(9).unwrap_err(),
+        TryAcquireError::Paused
+    );
+    let mut late = Box::pin(semaphore.acquire(0));
+    assert!(poll_once(late.as_mut()).is_pending());
+    assert_eq!(semaphore.queued(), 3);
+
+    semaphore.resume();
+    assert!(!semaphore.is_paused());
+    let high = high.await.unwrap();
+    assert!(poll_once(low.as_mut()).is_pending());
+    drop(high);
+    drop(low.await.unwrap());
+    drop(late.await.unwrap());
+    assert_eq!(semaphore.try_acquire(0).unwrap().num_permits(), 1);
+}
+
+#[tokio::test]
+async fn selected_waiters_are_cancelled_and_the_rest_keep_their_place() {
+    let semaphore = Arc::new(PrioritySemaphore::new(2));
+    let held = semaphore.acquire_many(2, 0).await.unwrap();
+
+    let mut bulk = Box::pin(semaphore.acquire_many(2, -5));
+    let mut batch = Box::pin(semaphore.acquire(-1));
+    let mut offboarded = Box::pin(semaphore.acquire_for(7, 1, 10).with_tag(7));
+    let mut kept = Box::pin(semaphore.acquire_for(8, 1, 10).with_tag(8));
+    let mut tagged = Box::pin(semaphore.acquire(4).with_tag(7));
+    let mut plain = Box::pin(semaphore.acquire(3));
+    for future in [
+        bulk.as_mut(),
+        batch.as_mut(),
+        tagged.as_mut(),
+        plain.as_mut(),
+    ] {
+        assert!(poll_once(future).is_pending());
+    }
+    assert!(poll_once(offboarded.as_mut()).is_pending());
+    assert!(poll_once(kept.as_mut()).is_pending());
+
+    // Tags are independent of tenants and fair sharing.
+    assert_eq!(semaphore.cancel_waiters_where(|_, tag| tag == Some(7)), 2);
+    assert_eq!(offboarded.await.unwrap_err(), AcquireError::Cancelled);
+    assert_eq!(tagged.await.unwrap_err(), AcquireError::Cancelled);
+    assert_eq!(semaphore.cancel_below(0), 2);
+    assert_eq!(bulk.await.unwrap_err(), AcquireError::Cancelled);
+    assert_eq!(batch.await.unwrap_err(), AcquireError::Cancelled);
+    assert_eq!(semaphore.cancel_below(0), 0);
+    assert_eq!(semaphore.queued(), 2);
+
+    drop(held);
+    drop(kept.await.unwrap());
+    drop(plain.await.unwrap());
+}
+
 #[test]
 fn immediate_acquisition_zero_capacity_and_debug_state() {
     let semaphore = Arc::new(PrioritySemaphore::new(1));
@@ -210,5 +1802,11 @@ fn public_concurrency_types_are_send_and_sync() {
 
     assert_send_sync::<PrioritySemaphore>();
     assert_send_sync::<Permit>();
+    assert_send_sync::<PermitRef<'static>>();
     assert_send::<AcquireFuture>();
+    assert_send::<AcquireRefFuture<'static>>();
+    assert_send::<AcquirePreemptible>();
+    assert_send_sync::<PriorityHandle>();
+    assert_send_sync::<PrioritySemaphore<(Tier, u32)>>();
+    assert_send::<AcquireFuture<(Tier, u32)>>();
 }